@@ -0,0 +1,337 @@
+use crate::eeprom::EepromFile;
+use crate::emulate::{EmulateOptions, LedBoardEmulator};
+use crate::fallback::FallbackLighting;
+use crate::proxy::{InjectedReplies, PairSpec, ProxyOptions};
+use crate::serial::SerialSettings;
+use crate::supervisor::FailurePolicy;
+use crate::tee::{Tee, TeeTarget};
+use crate::watchdog::Watchdog;
+use mailight_rs::jvs_parser::{JVSPacket, JvsNode};
+use mailight_rs::sega_led::{BoardInfo, BoardStatus, FetLevels, LEDCommand, ProtocolVersion};
+use serialport::{DataBits, FlowControl, Parity, StopBits};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+// A path in the temp dir unique to this test run, removed first in case of leftovers.
+fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("mailight_rs-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn test_eeprom_file() {
+    let path = temp_path("eeprom.bin");
+    std::fs::write(&path, [0x12, 0x34]).unwrap();
+
+    // Short files are padded out with blank bytes, on disk too.
+    let mut eeprom = EepromFile::open(&path).unwrap();
+    assert_eq!(eeprom.read(0x00), 0x12);
+    assert_eq!(eeprom.read(0x01), 0x34);
+    assert_eq!(eeprom.read(0x02), 0xFF);
+    assert_eq!(eeprom.read(0xFF), 0xFF);
+    assert_eq!(std::fs::read(&path).unwrap().len(), 256);
+
+    eeprom.write(0x80, 0x56).unwrap();
+    drop(eeprom);
+    let eeprom = EepromFile::open(&path).unwrap();
+    assert_eq!(eeprom.read(0x80), 0x56);
+    assert_eq!(eeprom.read(0x00), 0x12);
+
+    let mut memory = EepromFile::in_memory();
+    memory.write(0x01, 0x02).unwrap();
+    assert_eq!(memory.read(0x01), 0x02);
+    assert_eq!(memory.read(0x00), 0xFF);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_emulator_replies() {
+    let mut emulator = LedBoardEmulator::new(EmulateOptions {
+        alls_serial: SerialSettings::default(),
+        log_traffic: false,
+        board_info: BoardInfo::new("15070-04", 1),
+        protocol_version: "1.0".parse::<ProtocolVersion>().unwrap(),
+        board_status: BoardStatus::default(),
+        eeprom_file: None,
+    })
+    .unwrap();
+    let mut ask = |payload: Vec<u8>| {
+        let request = JVSPacket::with_payload(0x01, 0x02, payload).unwrap();
+        let reply = emulator.handle(&request).expect("a reply");
+        assert_eq!((reply.source_id, reply.dest_id), (0x02, 0x01));
+        reply.payload
+    };
+
+    // Acks carry no body, whatever the request had.
+    assert_eq!(ask(vec![0x32, 0, 8, 0, 1, 2, 3, 0]), vec![0x01, 0x32, 0x01]);
+    assert_eq!(ask(vec![0x7B, 0x10, 0xAB]), vec![0x01, 0x7B, 0x01]);
+    assert_eq!(ask(vec![0x7C, 0x10]), vec![0x01, 0x7C, 0x01, 0xAB]);
+    assert_eq!(ask(vec![0x11, 0x00, 0x05]), vec![0x01, 0x11, 0x01]);
+    assert_eq!(ask(vec![0xF1]), vec![0x01, 0xF1, 0x01, 0, 5, 0, 0]);
+    assert_eq!(ask(vec![0xF3]), vec![0x01, 0xF3, 0x01, 1, 1, 0]);
+    assert_eq!(&ask(vec![0xF0])[..11], b"\x01\xF0\x0115070-04");
+
+    // Malformed commands are refused, not ignored.
+    assert_eq!(ask(vec![0x32, 0, 8]), vec![0x01, 0x32, 0x04]);
+}
+
+#[test]
+fn test_injected_replies() {
+    let injected = InjectedReplies::new(115200);
+    let start = Instant::now();
+    injected.expect(
+        &[
+            (0x01, 0x02, LEDCommand::SetTimeout { seconds: 5 }),
+            (0x01, 0x02, LEDCommand::Commit),
+        ],
+        start,
+    );
+
+    // Only replies to what was sent are swallowed, each one once, in whatever order.
+    assert!(!injected.take(0x31, start));
+    assert!(injected.take(0x3C, start));
+    assert!(!injected.take(0x3C, start));
+    assert!(injected.take(0x11, start));
+    assert!(!injected.take(0x11, start));
+
+    // A reply the board never sent stops being waited for.
+    injected.expect(&[(0x01, 0x02, LEDCommand::Commit)], start);
+    assert!(!injected.take(0x3C, start + Duration::from_secs(3)));
+    injected.expect(&[(0x01, 0x02, LEDCommand::Commit)], start);
+    assert!(injected.take(0x3C, start + Duration::from_millis(900)));
+
+    // Slow ports get longer to answer a long replay.
+    let set = LEDCommand::SetMultiLED {
+        start: 0,
+        end: 1,
+        skip: 0,
+        r: 0,
+        g: 0,
+        b: 0,
+        speed: 0,
+    };
+    let replay = vec![(0x01, 0x02, set); 300];
+    let slow = InjectedReplies::new(9600);
+    slow.expect(&replay, start);
+    assert!(slow.take(0x32, start + Duration::from_secs(10)));
+    injected.expect(&replay, start);
+    assert!(!injected.take(0x32, start + Duration::from_secs(10)));
+}
+
+#[test]
+fn test_watchdog() {
+    let watchdog = Watchdog::new(Duration::from_secs(5));
+    let start = Instant::now();
+    let later = |secs| start + Duration::from_secs(secs);
+    assert_eq!(
+        watchdog.expired(later(10)),
+        None,
+        "no trip before the game speaks"
+    );
+
+    watchdog.feed(0x01, 0x02, start);
+    assert_eq!(watchdog.expired(later(4)), None);
+    assert_eq!(watchdog.expired(later(6)), Some((0x01, 0x02)));
+    watchdog.feed(0x03, 0x04, later(6));
+    assert_eq!(watchdog.expired(later(10)), None);
+    assert_eq!(watchdog.last_ids(), Some((0x03, 0x04)));
+
+    watchdog.set_timeout(30);
+    assert_eq!(watchdog.timeout(), Duration::from_secs(30));
+    assert_eq!(watchdog.expired(later(12)), None);
+    assert_eq!(watchdog.expired(later(40)), Some((0x03, 0x04)));
+    // No timeout on the board still leaves ours.
+    watchdog.set_timeout(0);
+    assert_eq!(watchdog.timeout(), Duration::from_secs(5));
+    assert_eq!(watchdog.expired(later(12)), Some((0x03, 0x04)));
+}
+
+#[test]
+fn test_serial_settings() {
+    assert_eq!(
+        "".parse::<SerialSettings>().unwrap(),
+        SerialSettings::default()
+    );
+    let settings: SerialSettings =
+        "baud=38400, data=7,parity=even,stop=2,flow=hardware,timeout=250,dtr=on,rts=off"
+            .parse()
+            .unwrap();
+    assert_eq!(
+        settings,
+        SerialSettings {
+            baud_rate: 38400,
+            data_bits: DataBits::Seven,
+            parity: Parity::Even,
+            stop_bits: StopBits::Two,
+            flow_control: FlowControl::Hardware,
+            timeout: Duration::from_millis(250),
+            dtr: Some(true),
+            rts: Some(false),
+        }
+    );
+
+    for bad in [
+        "baud",
+        "baud=fast",
+        "data=9",
+        "parity=mark",
+        "stop=3",
+        "flow=xon",
+        "timeout=0",
+        "dtr=1",
+        "speed=9600",
+    ] {
+        assert!(bad.parse::<SerialSettings>().is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn test_pair_spec() {
+    let spec: PairSpec = "name=2P,alls=/dev/ttyUSB2,led=/dev/ttyUSB3,fix_rbg,ring=0-1,ring=0-2,led_baud=38400,led_parity=even,alls_dtr=on"
+        .parse()
+        .unwrap();
+    assert_eq!(spec.name.as_deref(), Some("2P"));
+    assert_eq!(spec.alls_port, PathBuf::from("/dev/ttyUSB2"));
+    assert_eq!(spec.led_port, PathBuf::from("/dev/ttyUSB3"));
+    assert_eq!(spec.fix_rbg, Some(true));
+    assert_eq!(spec.ring, Some(vec!["0-1".to_owned(), "0-2".to_owned()]));
+    assert_eq!(spec.side, None);
+
+    // Serial settings land on top of the first pair's, and only for their own port.
+    let options = ProxyOptions {
+        name: "1P".to_owned(),
+        alls_serial: SerialSettings::default(),
+        led_serial: "stop=2".parse().unwrap(),
+        fix_rbg: true,
+        log_traffic: false,
+        board_info: None,
+        protocol_version: None,
+        board_status: None,
+        eeprom_file: None,
+        fallback: None,
+        watchdog_timeout: Duration::from_secs(5),
+        on_failure: FailurePolicy::Shutdown,
+        final_lighting: FallbackLighting::Off,
+        status_poll_interval: None,
+        tee: Vec::new(),
+    };
+    let first = PairSpec {
+        alls_port: "/dev/ttyUSB0".into(),
+        led_port: "/dev/ttyUSB1".into(),
+        ..Default::default()
+    };
+    // PWM pins need sysfs.
+    let spec = PairSpec {
+        ring: None,
+        fix_rbg: None,
+        ..spec
+    };
+    let third: PairSpec = "alls=a,led=b,fix_rbg=off".parse().unwrap();
+    let pairs = crate::build_pairs(options, first, vec![spec, third]).unwrap();
+    assert_eq!(pairs[0].options.led_serial.baud_rate, 115200);
+    let second = &pairs[1].options;
+    assert_eq!(second.name, "2P");
+    assert_eq!(second.led_serial.baud_rate, 38400);
+    assert_eq!(second.led_serial.parity, Parity::Even);
+    assert_eq!(second.led_serial.stop_bits, StopBits::Two);
+    assert_eq!(second.led_serial.dtr, None);
+    assert_eq!(second.alls_serial.dtr, Some(true));
+    assert_eq!(second.alls_serial.baud_rate, 115200);
+    // `fix_rbg` carries over unless the pair turns it off.
+    assert!(second.fix_rbg);
+    assert_eq!(pairs[2].options.name, "3P");
+    assert!(!pairs[2].options.fix_rbg);
+
+    for bad in [
+        "alls=/dev/ttyUSB2",
+        "led=/dev/ttyUSB3",
+        "alls=a,led=b,led_baud=fast",
+        "alls=a,led=b,led_timeout=0",
+        "alls=a,led=b,tee_baud=9600",
+        "alls=a,led=b,colour=red",
+        "alls=a,led=b,fix_rbg=yes",
+    ] {
+        assert!(bad.parse::<PairSpec>().is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn test_tee_target() {
+    let tee: TeeTarget = "port=/dev/ttyUSB4,order=brg,brightness=50".parse().unwrap();
+    assert_eq!(tee.port, PathBuf::from("/dev/ttyUSB4"));
+    assert_eq!(
+        tee.transform(&LEDCommand::SetLED {
+            index: 3,
+            r: 10,
+            g: 20,
+            b: 200,
+        }),
+        LEDCommand::SetLED {
+            index: 3,
+            r: 100,
+            g: 5,
+            b: 10,
+        }
+    );
+    assert_eq!(
+        tee.transform(&LEDCommand::SetFet(FetLevels {
+            chassis: 255,
+            ring: 100,
+            side: 1,
+        })),
+        LEDCommand::SetFet(FetLevels {
+            chassis: 127,
+            ring: 50,
+            side: 0,
+        })
+    );
+    assert_eq!(tee.transform(&LEDCommand::Commit), LEDCommand::Commit);
+
+    let plain: TeeTarget = "port=/dev/ttyUSB4".parse().unwrap();
+    let set = LEDCommand::SetLED {
+        index: 0,
+        r: 1,
+        g: 2,
+        b: 3,
+    };
+    assert_eq!(plain.transform(&set), set);
+
+    for bad in [
+        "order=grb",
+        "port=/dev/ttyUSB4,order=rrb",
+        "port=/dev/ttyUSB4,order=rgbw",
+        "port=/dev/ttyUSB4,brightness=101",
+        "port=/dev/ttyUSB4,gamma=2",
+    ] {
+        assert!(bad.parse::<TeeTarget>().is_err(), "{}", bad);
+    }
+
+    // Each pair can have its own tees, with settings for the latest `tee_port`.
+    let spec: PairSpec = "alls=a,led=b,tee_port=c,tee_brightness=20,tee_port=d,tee_order=gbr"
+        .parse()
+        .unwrap();
+    assert_eq!(
+        spec.tee,
+        vec![
+            "port=c,brightness=20".parse::<TeeTarget>().unwrap(),
+            "port=d,order=gbr".parse::<TeeTarget>().unwrap(),
+        ]
+    );
+    assert!("alls=a,led=b,tee_order=grb".parse::<PairSpec>().is_err());
+    assert!("alls=a,led=b,tee_port=".parse::<PairSpec>().is_err());
+}
+
+#[test]
+fn test_missing_tee_port() {
+    // A tee that isn't plugged in waits for its port instead of failing the pair.
+    let target = TeeTarget {
+        port: temp_path("missing-tee"),
+        .."port=unused".parse().unwrap()
+    };
+    let tee = Tee::open(target, SerialSettings::default());
+    assert_eq!(tee.port.reconnects(), 0);
+    tee.send_commands(&[(0x01, 0x02, LEDCommand::Commit)])
+        .unwrap();
+}
@@ -0,0 +1,63 @@
+use anyhow::Result;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const EEPROM_SIZE: usize = 256; // Addresses are a single byte.
+const BLANK: u8 = 0xFF; // What an erased EEPROM reads back as.
+
+/// Stand-in for the board's EEPROM, kept in a local file so its contents outlive the board.
+pub struct EepromFile {
+    file: Option<File>,
+    data: [u8; EEPROM_SIZE],
+}
+impl EepromFile {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        // Anything past the end of a short (or new) file reads as blank.
+        let mut data = [BLANK; EEPROM_SIZE];
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+        let len = contents.len().min(EEPROM_SIZE);
+        data[..len].copy_from_slice(&contents[..len]);
+        if contents.len() < EEPROM_SIZE {
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(&data)?;
+            file.sync_data()?;
+        }
+
+        Ok(EepromFile {
+            file: Some(file),
+            data,
+        })
+    }
+
+    /// A blank EEPROM that only lasts as long as the process.
+    pub fn in_memory() -> Self {
+        EepromFile {
+            file: None,
+            data: [BLANK; EEPROM_SIZE],
+        }
+    }
+
+    pub fn read(&self, address: u8) -> u8 {
+        self.data[address as usize]
+    }
+
+    pub fn write(&mut self, address: u8, data: u8) -> Result<()> {
+        self.data[address as usize] = data;
+        let Some(file) = &mut self.file else {
+            return Ok(());
+        };
+        file.seek(SeekFrom::Start(address as u64))?;
+        file.write_all(&[data])?;
+        file.sync_data()?; // The game only writes on calibration; make sure it sticks.
+        Ok(())
+    }
+}
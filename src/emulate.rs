@@ -0,0 +1,130 @@
+use crate::eeprom::EepromFile;
+use crate::proxy::READ_BUFFER_SIZE;
+use crate::serial::{ReconnectingPort, SerialSettings};
+use anyhow::Result;
+use mailight_rs::jvs_parser::{JVSPacket, JvsNode, SegaJVSReader};
+use mailight_rs::sega_led::{
+    BoardInfo, BoardStatus, LEDCommand, LEDReply, LEDReplyBody, ProtocolVersion, ReplyReport,
+    ReplyStatus,
+};
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+
+pub struct EmulateOptions {
+    pub alls_serial: SerialSettings,
+    pub log_traffic: bool,
+    pub board_info: BoardInfo,
+    pub protocol_version: ProtocolVersion,
+    pub board_status: BoardStatus,
+    /// Keep the EEPROM in this file. `None` starts blank every run.
+    pub eeprom_file: Option<PathBuf>,
+}
+
+/// Answers the game the way a 15070 LED board would, with no board attached.
+pub struct LedBoardEmulator {
+    options: EmulateOptions,
+    eeprom: EepromFile,
+}
+impl LedBoardEmulator {
+    pub fn new(options: EmulateOptions) -> Result<Self> {
+        let eeprom = match &options.eeprom_file {
+            Some(path) => EepromFile::open(path)?,
+            None => EepromFile::in_memory(),
+        };
+        Ok(LedBoardEmulator { options, eeprom })
+    }
+
+    /// What the board would answer `request` with.
+    fn reply_to(&mut self, request: LEDCommand) -> LEDReply {
+        let body = match &request {
+            LEDCommand::GetBoardInfoCommand(_) => {
+                LEDReplyBody::BoardInfo(self.options.board_info.clone())
+            }
+            LEDCommand::GetProtocolVersionCommand(_) => {
+                LEDReplyBody::ProtocolVersion(self.options.protocol_version)
+            }
+            LEDCommand::GetBoardStatusCommand(_) => {
+                LEDReplyBody::BoardStatus(self.options.board_status)
+            }
+            LEDCommand::EepromRead { address } => {
+                LEDReplyBody::EepromRead(self.eeprom.read(*address))
+            }
+            LEDCommand::EepromWrite { address, data } => {
+                if let Err(err) = self.eeprom.write(*address, *data) {
+                    tracing::error!("Couldn't save EEPROM write to {:#04x}: {:?}", address, err);
+                }
+                LEDReplyBody::Ack
+            }
+            LEDCommand::SetTimeout { seconds } => {
+                self.options.board_status.timeout_sec = (*seconds).min(u8::MAX as u16) as u8;
+                LEDReplyBody::Ack
+            }
+            LEDCommand::Unknown { opcode, .. } => {
+                tracing::warn!("Acking unknown LED command {:#04x}", opcode);
+                LEDReplyBody::Ack
+            }
+            _ => LEDReplyBody::Ack,
+        };
+        LEDReply {
+            status: ReplyStatus::Ok,
+            opcode: request.opcode(),
+            report: ReplyReport::Ok,
+            body,
+        }
+    }
+}
+impl JvsNode for LedBoardEmulator {
+    fn handle(&mut self, request: &JVSPacket) -> Option<JVSPacket> {
+        let reply = match LEDCommand::parse(request) {
+            Ok(cmd) => {
+                if self.options.log_traffic {
+                    tracing::info!("LED command: {:?}", cmd);
+                }
+                self.reply_to(cmd)
+            }
+            Err(err) => {
+                tracing::error!("Couldn't parse: {:?}", err);
+                // Nothing to answer without even an opcode.
+                let opcode = *request.payload.first()?;
+                LEDReply {
+                    status: ReplyStatus::Ok,
+                    opcode,
+                    report: ReplyReport::ParameterError,
+                    body: LEDReplyBody::Ack,
+                }
+            }
+        };
+        let mut response = request.reply();
+        reply.serialize_to_jvs(&mut response);
+        Some(response)
+    }
+}
+
+pub fn emulate(alls_port: PathBuf, options: EmulateOptions, stop: &AtomicBool) -> Result<()> {
+    let port = ReconnectingPort::open("ALLS", alls_port.clone(), options.alls_serial.clone())?;
+    tracing::info!("Emulating {} on {:?}", options.board_info, alls_port);
+    let mut emulator = LedBoardEmulator::new(options)?;
+
+    let mut jvs_reader = SegaJVSReader::default();
+    let mut buf = [0u8; READ_BUFFER_SIZE];
+    let mut reply_buffer = Vec::new();
+    while let Some(len) = port.read(&mut buf, stop) {
+        reply_buffer.clear();
+        for result in jvs_reader.feed(&buf[..len]) {
+            let packet = match result {
+                Ok(packet) => packet,
+                Err(err) => {
+                    tracing::error!("Bad JVS data from ALLS: {}", err);
+                    continue;
+                }
+            };
+            if let Some(reply) = emulator.handle(&packet) {
+                reply.serialize(&mut reply_buffer)?;
+            }
+        }
+        if !reply_buffer.is_empty() {
+            port.write_all(&reply_buffer);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,89 @@
+use anyhow::{bail, Result};
+use mailight_rs::sega_led::{FetLevels, LEDCommand};
+use std::str::FromStr;
+use std::time::Duration;
+
+const ALL_LEDS_END: u8 = 0xFF; // End of a run covering every LED the board has.
+const DIM_LEVEL: u8 = 0x20;
+const FADE_SPEED: u8 = 0x40;
+const IDLE_STEP: Duration = Duration::from_secs(3);
+
+/// What to show when we stop hearing from the game.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FallbackLighting {
+    Off,
+    Dim,
+    FadeOut,
+    /// Breathes slowly between dim white and off.
+    Idle,
+}
+impl FromStr for FallbackLighting {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "off" => FallbackLighting::Off,
+            "dim" => FallbackLighting::Dim,
+            "fade" => FallbackLighting::FadeOut,
+            "idle" => FallbackLighting::Idle,
+            _ => bail!("Unknown fallback `{}`, expected off, dim, fade or idle", s),
+        })
+    }
+}
+impl FallbackLighting {
+    /// How often `frame` should be re-sent, for fallbacks that animate.
+    pub fn refresh_interval(&self) -> Option<Duration> {
+        match self {
+            FallbackLighting::Idle => Some(IDLE_STEP),
+            _ => None,
+        }
+    }
+
+    pub fn fet_levels(&self) -> FetLevels {
+        let level = match self {
+            FallbackLighting::Dim | FallbackLighting::Idle => DIM_LEVEL,
+            FallbackLighting::Off | FallbackLighting::FadeOut => 0,
+        };
+        FetLevels {
+            chassis: level,
+            ring: level,
+            side: level,
+        }
+    }
+
+    /// Commands that put every LED into this state. `step` counts refreshes, for animation.
+    pub fn commands(&self, step: u32) -> Vec<LEDCommand> {
+        let (level, speed) = match self {
+            FallbackLighting::Off => (0, None),
+            FallbackLighting::Dim => (DIM_LEVEL, None),
+            FallbackLighting::FadeOut => (0, Some(FADE_SPEED)),
+            FallbackLighting::Idle if step.is_multiple_of(2) => (DIM_LEVEL, Some(FADE_SPEED)),
+            FallbackLighting::Idle => (0, Some(FADE_SPEED)),
+        };
+        let set = match speed {
+            None => LEDCommand::SetMultiLED {
+                start: 0,
+                end: ALL_LEDS_END,
+                skip: 0,
+                r: level,
+                g: level,
+                b: level,
+                speed: 0,
+            },
+            Some(speed) => LEDCommand::SetMultiLEDFade {
+                start: 0,
+                end: ALL_LEDS_END,
+                skip: 0,
+                r: level,
+                g: level,
+                b: level,
+                speed,
+            },
+        };
+        vec![
+            set,
+            LEDCommand::SetFet(self.fet_levels()),
+            LEDCommand::Commit,
+        ]
+    }
+}
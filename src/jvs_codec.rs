@@ -0,0 +1,56 @@
+use crate::jvs_parser::{JVSPacket, JvsErrorCounts, SegaJVSReader};
+use bytes::{Buf, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// `tokio_util` codec for JVS frames, driven by the same state machine as `SegaJVSReader`.
+///
+/// Framing errors are logged and counted rather than returned, so a noisy line doesn't end the
+/// stream. Check `error_counts` (e.g. via `Framed::codec`) to see what was dropped.
+#[derive(Default)]
+pub struct JvsCodec {
+    reader: SegaJVSReader,
+}
+impl JvsCodec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn error_counts(&self) -> &JvsErrorCounts {
+        self.reader.error_counts()
+    }
+}
+
+impl Decoder for JvsCodec {
+    type Item = JVSPacket;
+    type Error = std::io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let mut consumed = 0;
+        let mut packet = None;
+        for byte in src.iter() {
+            consumed += 1;
+            match self.reader.read_byte(*byte) {
+                Ok(Some(complete)) => {
+                    packet = Some(complete);
+                    break;
+                }
+                Ok(None) => (),
+                Err(err) => tracing::warn!("Bad JVS data: {}", err),
+            }
+        }
+        src.advance(consumed);
+        Ok(packet)
+    }
+}
+
+impl Encoder<JVSPacket> for JvsCodec {
+    type Error = std::io::Error;
+
+    fn encode(&mut self, item: JVSPacket, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let mut buf = Vec::new();
+        item.serialize(&mut buf)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+        dst.extend_from_slice(&buf);
+        Ok(())
+    }
+}
@@ -1,58 +1,139 @@
+use std::collections::BTreeMap;
+
 const SYNC_BYTE: u8 = 0xE0;
 const ESCAPE_BYTE: u8 = 0xD0;
 
+// Every byte after the sync byte goes through here, checksum included.
+fn escape(byte: u8, output: &mut Vec<u8>) {
+    if byte == SYNC_BYTE || byte == ESCAPE_BYTE {
+        output.extend_from_slice(&[ESCAPE_BYTE, byte - 1]);
+    } else {
+        output.push(byte);
+    }
+}
+
 fn escape_and_push(input: &[u8], output: &mut Vec<u8>, checksum: &mut u8) {
     for byte in input {
         *checksum = checksum.wrapping_add(*byte);
-        if *byte == SYNC_BYTE {
-            output.extend_from_slice(&[ESCAPE_BYTE, byte - 1]);
-        } else {
-            output.push(*byte);
-        }
+        escape(*byte, output);
     }
 }
 
-#[derive(Clone, Default, PartialEq)]
+/// Largest payload the single length byte of a frame can describe.
+pub const MAX_PAYLOAD_LEN: usize = u8::MAX as usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PayloadTooLong(pub usize);
+impl std::fmt::Display for PayloadTooLong {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "JVS payload is {} bytes, the maximum is {}",
+            self.0, MAX_PAYLOAD_LEN
+        )
+    }
+}
+impl std::error::Error for PayloadTooLong {}
+
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct JVSPacket {
     pub source_id: u8,
     pub dest_id: u8,
-    pub expected_len: u8,
     pub payload: Vec<u8>,
-    pub checksum: u8,
 }
 impl JVSPacket {
     pub fn new(source: u8, dest: u8) -> Self {
         Self {
             source_id: source,
             dest_id: dest,
-            expected_len: 0,
             payload: Vec::new(),
-            checksum: 0,
         }
     }
 
-    pub fn serialize(&mut self, buf: &mut Vec<u8>) {
+    pub fn with_payload(source: u8, dest: u8, payload: Vec<u8>) -> Result<Self, PayloadTooLong> {
+        if payload.len() > MAX_PAYLOAD_LEN {
+            return Err(PayloadTooLong(payload.len()));
+        }
+        Ok(Self {
+            source_id: source,
+            dest_id: dest,
+            payload,
+        })
+    }
+
+    /// Empty packet addressed back to the sender of `self`.
+    pub fn reply(&self) -> Self {
+        Self::new(self.dest_id, self.source_id)
+    }
+
+    /// Writes the framed packet to `buf`. Nothing is written if the payload doesn't fit in a
+    /// frame.
+    pub fn serialize(&self, buf: &mut Vec<u8>) -> Result<(), PayloadTooLong> {
+        let len =
+            u8::try_from(self.payload.len()).map_err(|_| PayloadTooLong(self.payload.len()))?;
         buf.push(SYNC_BYTE);
         let mut checksum = 0u8;
-        self.expected_len = self.payload.len() as u8;
-        escape_and_push(
-            &[self.dest_id, self.source_id, self.expected_len],
-            buf,
-            &mut checksum,
-        );
+        escape_and_push(&[self.dest_id, self.source_id, len], buf, &mut checksum);
         escape_and_push(&self.payload, buf, &mut checksum);
-        buf.push(checksum);
+        escape(checksum, buf);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JvsError {
+    /// The trailing checksum byte didn't match the sum of the frame.
+    BadChecksum { expected: u8, actual: u8 },
+    /// A sync byte arrived before the current frame was complete; the partial frame was dropped.
+    UnexpectedSync,
+    /// A byte arrived after a complete frame but before the next sync byte.
+    DataWhileReady(u8),
+    /// A byte arrived before the reader ever saw a sync byte.
+    GarbageBeforeSync(u8),
+}
+impl std::fmt::Display for JvsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JvsError::BadChecksum { expected, actual } => write!(
+                f,
+                "Bad checksum: expected {:#04x}, got {:#04x}",
+                expected, actual
+            ),
+            JvsError::UnexpectedSync => write!(f, "Got sync byte in the middle of a frame"),
+            JvsError::DataWhileReady(byte) => write!(f, "Got data in ready state: {:#04x}", byte),
+            JvsError::GarbageBeforeSync(byte) => write!(f, "Got data before sync: {:#04x}", byte),
+        }
+    }
+}
+impl std::error::Error for JvsError {}
+
+/// Number of errors of each kind a reader has seen over its lifetime.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct JvsErrorCounts {
+    pub bad_checksum: u64,
+    pub unexpected_sync: u64,
+    pub data_while_ready: u64,
+    pub garbage_before_sync: u64,
+}
+impl JvsErrorCounts {
+    fn record(&mut self, err: &JvsError) {
+        match err {
+            JvsError::BadChecksum { .. } => self.bad_checksum += 1,
+            JvsError::UnexpectedSync => self.unexpected_sync += 1,
+            JvsError::DataWhileReady(_) => self.data_while_ready += 1,
+            JvsError::GarbageBeforeSync(_) => self.garbage_before_sync += 1,
+        }
     }
 }
 
 #[derive(Clone, Copy, PartialEq, Default)]
 enum ReaderState {
     #[default]
+    Sync,
     Src,
     Dest,
     Len,
     Payload,
-    PayloadEscaped,
     Checksum,
     Ready,
 }
@@ -61,70 +142,234 @@ enum ReaderState {
 pub struct SegaJVSReader {
     state: ReaderState,
     packet: JVSPacket,
+    expected_len: u8,
+    checksum: u8,
+    escaped: bool,
+    errors: JvsErrorCounts,
 }
 impl SegaJVSReader {
     fn reset(&mut self) -> ReaderState {
         self.packet = JVSPacket::default();
+        self.expected_len = 0;
+        self.checksum = 0;
+        self.escaped = false;
         self.state = ReaderState::Dest;
         self.state
     }
 
-    pub fn read_byte(&mut self, mut input: u8) -> Option<&mut JVSPacket> {
+    pub fn error_counts(&self) -> &JvsErrorCounts {
+        &self.errors
+    }
+
+    /// Decodes a chunk of input, yielding each packet (or framing error) as it completes.
+    /// A partial frame at the end of `input` is kept and finished by the next call. Bytes the
+    /// iterator hasn't reached when it is dropped are not consumed.
+    pub fn feed<'r, 'b>(&'r mut self, input: &'b [u8]) -> Feed<'r, 'b> {
+        Feed {
+            reader: self,
+            input: input.iter(),
+        }
+    }
+
+    pub fn read_byte(&mut self, input: u8) -> Result<Option<JVSPacket>, JvsError> {
+        let result = self.advance(input);
+        if let Err(err) = &result {
+            self.errors.record(err);
+        }
+        result
+    }
+
+    fn advance(&mut self, mut input: u8) -> Result<Option<JVSPacket>, JvsError> {
         if input == SYNC_BYTE {
+            let interrupted = !matches!(self.state, ReaderState::Sync | ReaderState::Ready);
             self.reset();
-            return None;
+            return if interrupted {
+                Err(JvsError::UnexpectedSync)
+            } else {
+                Ok(None)
+            };
+        }
+
+        match self.state {
+            ReaderState::Sync => return Err(JvsError::GarbageBeforeSync(input)),
+            ReaderState::Ready => return Err(JvsError::DataWhileReady(input)),
+            _ => (),
+        }
+
+        if self.escaped {
+            self.escaped = false;
+            input = input.wrapping_add(1);
+        } else if input == ESCAPE_BYTE {
+            self.escaped = true;
+            return Ok(None);
         }
 
         self.state = match self.state {
+            ReaderState::Sync | ReaderState::Ready => unreachable!(),
             ReaderState::Src => {
                 self.packet.source_id = input;
-                self.packet.checksum = self.packet.checksum.wrapping_add(input);
+                self.checksum = self.checksum.wrapping_add(input);
                 ReaderState::Len
             }
             ReaderState::Dest => {
                 self.packet.dest_id = input;
-                self.packet.checksum = self.packet.checksum.wrapping_add(input);
+                self.checksum = self.checksum.wrapping_add(input);
                 ReaderState::Src
             }
             ReaderState::Len => {
-                self.packet.expected_len = input;
-                self.packet.checksum = self.packet.checksum.wrapping_add(input);
-                ReaderState::Payload
-            }
-            ReaderState::PayloadEscaped | ReaderState::Payload => 'b: {
-                if input == ESCAPE_BYTE {
-                    break 'b ReaderState::PayloadEscaped;
-                }
-
-                if self.state == ReaderState::PayloadEscaped {
-                    input += 1;
+                self.expected_len = input;
+                self.checksum = self.checksum.wrapping_add(input);
+                if input == 0 {
+                    ReaderState::Checksum
+                } else {
+                    ReaderState::Payload
                 }
-
+            }
+            ReaderState::Payload => {
                 self.packet.payload.push(input);
-                self.packet.checksum = self.packet.checksum.wrapping_add(input);
-                if self.packet.payload.len() == self.packet.expected_len as usize {
+                self.checksum = self.checksum.wrapping_add(input);
+                if self.packet.payload.len() == self.expected_len as usize {
                     ReaderState::Checksum
                 } else {
                     ReaderState::Payload
                 }
             }
             ReaderState::Checksum => {
-                if self.packet.checksum == input {
-                    ReaderState::Ready
-                } else {
-                    tracing::error!("Bad checksum");
-                    self.reset()
+                if self.checksum != input {
+                    self.state = ReaderState::Sync;
+                    return Err(JvsError::BadChecksum {
+                        expected: self.checksum,
+                        actual: input,
+                    });
                 }
-            }
-            ReaderState::Ready => {
-                tracing::warn!("Got data in ready state: {:#04x}", input);
-                ReaderState::Ready
+                self.state = ReaderState::Ready;
+                return Ok(Some(std::mem::take(&mut self.packet)));
             }
         };
 
-        match self.state {
-            ReaderState::Ready => Some(&mut self.packet),
-            _ => None,
+        Ok(None)
+    }
+}
+
+pub struct Feed<'r, 'b> {
+    reader: &'r mut SegaJVSReader,
+    input: std::slice::Iter<'b, u8>,
+}
+impl Iterator for Feed<'_, '_> {
+    type Item = Result<JVSPacket, JvsError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for byte in self.input.by_ref() {
+            match self.reader.read_byte(*byte) {
+                Ok(Some(packet)) => return Some(Ok(packet)),
+                Ok(None) => (),
+                Err(err) => return Some(Err(err)),
+            }
+        }
+        None
+    }
+}
+
+pub const HOST_ADDRESS: u8 = 0x00;
+pub const BROADCAST_ADDRESS: u8 = 0xFF;
+
+/// Something living at an address on a `JvsBus`.
+pub trait JvsNode {
+    /// Handles a packet sent to this node's address or to the broadcast address. Replies are sent
+    /// from the node's own address, whatever `source_id` they were built with.
+    fn handle(&mut self, request: &JVSPacket) -> Option<JVSPacket>;
+}
+impl<F: FnMut(&JVSPacket) -> Option<JVSPacket>> JvsNode for F {
+    fn handle(&mut self, request: &JVSPacket) -> Option<JVSPacket> {
+        self(request)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JvsBusError {
+    /// The host and broadcast addresses can't be given to a node.
+    ReservedAddress(u8),
+    AddressInUse(u8),
+    /// Every node address is taken.
+    BusFull,
+}
+impl std::fmt::Display for JvsBusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JvsBusError::ReservedAddress(address) => {
+                write!(f, "Address {:#04x} is reserved", address)
+            }
+            JvsBusError::AddressInUse(address) => {
+                write!(f, "Address {:#04x} is already in use", address)
+            }
+            JvsBusError::BusFull => write!(f, "No free node addresses left on the bus"),
         }
     }
 }
+impl std::error::Error for JvsBusError {}
+
+/// Routes packets to the virtual nodes sharing one line, by `dest_id`.
+#[derive(Default)]
+pub struct JvsBus {
+    nodes: BTreeMap<u8, Box<dyn JvsNode + Send>>,
+}
+impl JvsBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Attaches `node` at the lowest free address and returns it.
+    pub fn attach(&mut self, node: impl JvsNode + Send + 'static) -> Result<u8, JvsBusError> {
+        let address = (HOST_ADDRESS + 1..BROADCAST_ADDRESS)
+            .find(|address| !self.nodes.contains_key(address))
+            .ok_or(JvsBusError::BusFull)?;
+        self.nodes.insert(address, Box::new(node));
+        Ok(address)
+    }
+
+    pub fn attach_at(
+        &mut self,
+        address: u8,
+        node: impl JvsNode + Send + 'static,
+    ) -> Result<(), JvsBusError> {
+        if address == HOST_ADDRESS || address == BROADCAST_ADDRESS {
+            return Err(JvsBusError::ReservedAddress(address));
+        }
+        if self.nodes.contains_key(&address) {
+            return Err(JvsBusError::AddressInUse(address));
+        }
+        self.nodes.insert(address, Box::new(node));
+        Ok(())
+    }
+
+    pub fn detach(&mut self, address: u8) -> Option<Box<dyn JvsNode + Send>> {
+        self.nodes.remove(&address)
+    }
+
+    pub fn addresses(&self) -> impl Iterator<Item = u8> + '_ {
+        self.nodes.keys().copied()
+    }
+
+    /// Hands `request` to the node it's addressed to, or to every node for a broadcast.
+    /// Returns `None` if no node lives at `dest_id`, so the caller can send it elsewhere.
+    pub fn dispatch(&mut self, request: &JVSPacket) -> Option<Vec<JVSPacket>> {
+        let mut replies = Vec::new();
+        if request.dest_id == BROADCAST_ADDRESS {
+            for (address, node) in self.nodes.iter_mut() {
+                replies.extend(node.handle(request).map(|reply| from_node(*address, reply)));
+            }
+        } else {
+            let node = self.nodes.get_mut(&request.dest_id)?;
+            replies.extend(
+                node.handle(request)
+                    .map(|reply| from_node(request.dest_id, reply)),
+            );
+        }
+        Some(replies)
+    }
+}
+
+fn from_node(address: u8, mut reply: JVSPacket) -> JVSPacket {
+    reply.source_id = address;
+    reply
+}
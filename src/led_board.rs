@@ -0,0 +1,231 @@
+use crate::sega_led::{led_range, DotCorrection, FetLevels, LEDCommand};
+use std::time::Duration;
+
+/// Addresses are a single byte, so this covers every LED a command can name.
+pub const LED_COUNT: usize = 256;
+/// Dot correction is 6 bits per channel; the board powers up at full current.
+pub const DEFAULT_DOT_CORRECTION: [u8; 3] = [0x3F; 3];
+// Fade length is inversely proportional to its speed byte: 0xFF is roughly 130ms, 0x01 over 30s.
+const FADE_SCALE_MS: u64 = 32768;
+
+pub fn fade_duration(speed: u8) -> Duration {
+    match speed {
+        0 => Duration::ZERO,
+        speed => Duration::from_millis(FADE_SCALE_MS / speed as u64),
+    }
+}
+
+/// Colour of one LED: fading from `from` to `to` over `length`, beginning at `started`.
+#[derive(Debug, Clone, Copy, Default)]
+struct LedState {
+    from: [u8; 3],
+    to: [u8; 3],
+    started: Duration,
+    length: Duration,
+}
+impl LedState {
+    fn at(&self, now: Duration) -> [u8; 3] {
+        let elapsed = now.saturating_sub(self.started);
+        if elapsed >= self.length {
+            return self.to;
+        }
+        let progress = elapsed.as_secs_f32() / self.length.as_secs_f32();
+        let mut color = [0; 3];
+        for (channel, (from, to)) in color.iter_mut().zip(self.from.iter().zip(self.to)) {
+            *channel = (*from as f32 + (to as f32 - *from as f32) * progress).round() as u8;
+        }
+        color
+    }
+}
+
+/// Set but not yet committed: the colour an LED heads to on the next `Commit`, and how long
+/// it takes to get there.
+#[derive(Debug, Clone, Copy)]
+struct Pending {
+    color: [u8; 3],
+    fade: Duration,
+}
+
+/// What the LED board is showing, rebuilt from the commands sent to it.
+///
+/// Colour commands are buffered until `Commit`, like on the board; FET levels and dot
+/// correction apply straight away. Time only moves when `advance` is called, so the same
+/// stream of commands always gives the same colours.
+#[derive(Debug, Clone)]
+pub struct VirtualLedBoard {
+    now: Duration,
+    leds: [LedState; LED_COUNT],
+    pending: [Option<Pending>; LED_COUNT],
+    dot_correction: [[u8; 3]; LED_COUNT],
+    fet: FetLevels,
+}
+impl Default for VirtualLedBoard {
+    fn default() -> Self {
+        VirtualLedBoard {
+            now: Duration::ZERO,
+            leds: [LedState::default(); LED_COUNT],
+            pending: [None; LED_COUNT],
+            dot_correction: [DEFAULT_DOT_CORRECTION; LED_COUNT],
+            fet: FetLevels::default(),
+        }
+    }
+}
+impl VirtualLedBoard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lets `elapsed` pass, moving any running fades along.
+    pub fn advance(&mut self, elapsed: Duration) {
+        self.now += elapsed;
+    }
+
+    /// Total time passed to `advance` since the board was created.
+    pub fn elapsed(&self) -> Duration {
+        self.now
+    }
+
+    pub fn apply(&mut self, command: &LEDCommand) {
+        match command {
+            LEDCommand::Reset => {
+                *self = VirtualLedBoard {
+                    now: self.now,
+                    ..Default::default()
+                }
+            }
+            LEDCommand::SetLED { index, r, g, b } => {
+                self.pending[*index as usize] = Some(Pending {
+                    color: [*r, *g, *b],
+                    fade: Duration::ZERO,
+                })
+            }
+            LEDCommand::SetMultiLED {
+                start,
+                end,
+                skip,
+                r,
+                g,
+                b,
+                ..
+            } => self.set_range(*start, *end, *skip, [*r, *g, *b], Duration::ZERO),
+            LEDCommand::SetMultiLEDFade {
+                start,
+                end,
+                skip,
+                r,
+                g,
+                b,
+                speed,
+            } => self.set_range(*start, *end, *skip, [*r, *g, *b], fade_duration(*speed)),
+            LEDCommand::SetDc(dc) | LEDCommand::UpdateDc(dc) => self.set_dot_correction(dc),
+            LEDCommand::SetFet(fet) => self.fet = *fet,
+            LEDCommand::Commit => self.commit(),
+            _ => (),
+        }
+    }
+
+    fn set_range(&mut self, start: u8, end: u8, skip: u8, color: [u8; 3], fade: Duration) {
+        for index in led_range(start, end, skip) {
+            self.pending[index as usize] = Some(Pending { color, fade });
+        }
+    }
+
+    fn set_dot_correction(&mut self, dc: &DotCorrection) {
+        for index in dc.leds() {
+            self.dot_correction[index as usize] = dc.channels();
+        }
+    }
+
+    fn commit(&mut self) {
+        let now = self.now;
+        for (led, pending) in self.leds.iter_mut().zip(self.pending.iter_mut()) {
+            if let Some(pending) = pending.take() {
+                *led = LedState {
+                    from: led.at(now),
+                    to: pending.color,
+                    started: now,
+                    length: pending.fade,
+                };
+            }
+        }
+    }
+
+    /// Colour LED `index` is showing right now, part way through any fade.
+    pub fn led(&self, index: u8) -> [u8; 3] {
+        self.leds[index as usize].at(self.now)
+    }
+
+    /// Colour of every LED right now, by index.
+    pub fn leds(&self) -> impl Iterator<Item = [u8; 3]> + '_ {
+        self.leds.iter().map(|led| led.at(self.now))
+    }
+
+    /// Whether any LED is still part way through a fade.
+    pub fn is_fading(&self) -> bool {
+        self.leds
+            .iter()
+            .any(|led| self.now.saturating_sub(led.started) < led.length)
+    }
+
+    /// Commands that bring a freshly reset board to this state: dot correction, FET levels,
+    /// then every LED at the colour it is showing or fading to, and a `Commit`.
+    pub fn replay_commands(&self) -> Vec<LEDCommand> {
+        // A run's `end` is exclusive, so runs stop short of the last LED; only `SetLED` reaches
+        // it, and nothing can set its dot correction.
+        let last = LED_COUNT - 1;
+        let mut commands = Vec::new();
+        for (start, end, [r, g, b]) in runs(self.dot_correction[..last].iter().copied()) {
+            commands.push(LEDCommand::SetDc(DotCorrection {
+                start,
+                end,
+                skip: 0,
+                r,
+                g,
+                b,
+                speed: 0,
+            }));
+        }
+        commands.push(LEDCommand::SetFet(self.fet));
+        for (start, end, [r, g, b]) in runs(self.leds[..last].iter().map(|led| led.to)) {
+            commands.push(LEDCommand::SetMultiLED {
+                start,
+                end,
+                skip: 0,
+                r,
+                g,
+                b,
+                speed: 0,
+            });
+        }
+        let [r, g, b] = self.leds[last].to;
+        commands.push(LEDCommand::SetLED {
+            index: last as u8,
+            r,
+            g,
+            b,
+        });
+        commands.push(LEDCommand::Commit);
+        commands
+    }
+
+    pub fn fet(&self) -> FetLevels {
+        self.fet
+    }
+
+    pub fn dot_correction(&self, index: u8) -> [u8; 3] {
+        self.dot_correction[index as usize]
+    }
+}
+
+/// Splits per-LED values into `(start, end, value)` runs of equal neighbours.
+fn runs(values: impl Iterator<Item = [u8; 3]>) -> Vec<(u8, u8, [u8; 3])> {
+    let mut runs: Vec<(u8, u8, [u8; 3])> = Vec::new();
+    for (index, value) in values.enumerate() {
+        let index = index as u8;
+        match runs.last_mut() {
+            Some((_, end, last)) if *last == value => *end = index + 1,
+            _ => runs.push((index, index + 1, value)),
+        }
+    }
+    runs
+}
@@ -1,10 +1,8 @@
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
-use std::io::empty;
-use std::iter::Map;
-use std::usize;
-use crate::Opts;
-use anyhow::Result;
-use sysfs_pwm::{Pwm, PwmChip};
+use anyhow::{anyhow, Result};
+use mailight_rs::sega_led::FetLevels;
+use sysfs_pwm::Pwm;
 use crate::led_pwm::PwmLedSection::{Chassis, Ring, Side};
 
 const PWM_PERIOD: u32 = 50_000;
@@ -26,123 +24,131 @@ const PWM_SPEED_MAPPING:[u32;256] = { // Generate our mappings at compile time;
     out
 };
 
-#[derive(Hash, Eq, PartialEq)]
+#[derive(Hash, Eq, PartialEq, Clone, Copy)]
 enum PwmLedSection {
     Ring,
     Chassis,
     Side,
 }
 
-struct PwmLedConfig {
-    map: HashMap<PwmLedSection, Vec<Pwm>>,
+type PinId = (u32, u32); // (pwmchip#, pwm#)
+
+pub struct PwmLedConfig {
+    map: HashMap<PwmLedSection, Vec<PinId>>,
+    pins: HashMap<PinId, Pwm>, // One handle per pin, however many sections share it.
+    open: bool, // Pins start at full duty, so they get closed on drop unless handed off with `close_pins` or `keep_pins`.
+}
+
+impl Drop for PwmLedConfig {
+    fn drop(&mut self) {
+        if self.open {
+            if let Err(err) = close_all(self) {
+                tracing::error!("Couldn't close PWM pins: {:?}", err);
+            }
+        }
+    }
 }
 
-fn create_config(
+pub fn create_config(
     ring: Option<Vec<String>>,
     side: Option<Vec<String>>,
     chassis: Option<Vec<String>>,
 ) -> Result<PwmLedConfig> {
     let mut out = PwmLedConfig{
         map: HashMap::new(),
+        pins: HashMap::new(),
+        open: true,
     };
 
-    fn apply_item(pin: Option<Vec<String>>, section: PwmLedSection, map: &mut HashMap<PwmLedSection, Vec<Pwm>>,) {
-        let out: Vec<Pwm> = Vec::new();
-        match pin {
-            Some(pins) => {
-                pins.iter().for_each(|pinstr| {
-                    let parts = pinstr.split('-');
-                })
-                // parse
-
-                // insert into list
-                // insert list into map
-
-            }
-            None => {
-                // No-op
+    fn apply_item(pin: Option<Vec<String>>, section: PwmLedSection, out: &mut PwmLedConfig) -> Result<()> {
+        for pinstr in pin.unwrap_or_default() {
+            // parse
+            let (chip, number) = pinstr
+                .split_once('-')
+                .ok_or_else(|| anyhow!("Bad PWM pin `{}`, expected `<pwmchip#>-<pwm#>`", pinstr))?;
+            let id: PinId = (chip.parse()?, number.parse()?);
+
+            // insert into list, and the list into the map
+            out.map.entry(section).or_default().push(id);
+            if let Entry::Vacant(entry) = out.pins.entry(id) {
+                entry.insert(Pwm::new(id.0, id.1)?);
             }
         }
+
+        Ok(())
     }
 
-    apply_item(ring, Ring, &mut out.map);
-    apply_item(side, Chassis, &mut out.map);
-    apply_item(chassis, Side, &mut out.map);
+    apply_item(ring, Ring, &mut out)?;
+    apply_item(side, Side, &mut out)?;
+    apply_item(chassis, Chassis, &mut out)?;
 
-    match initialize_pins(&out) {
-        Ok(()) => {}
-        Err(e) => return Err(e),
-    }
+    initialize_pins(&out)?;
 
     Ok(out)
 }
 
 fn initialize_pins(cfg: &PwmLedConfig) -> Result<()> {
-    // for key in cfg.map.keys() {
-    //     // Export it in pwm
-    //     // Set period
-    //     // Enable
-    // }
-    //
-    // // Set the pins all up at max default
-    // update_pins([255,255,255], cfg);
-
-    for (_, pins) in cfg.map.iter() {
-        for pin in pins {
-            pin.export()?; // Export if it isn't already
-            pin.set_period_ns(PWM_PERIOD)?; // Configure the period
-            pin.set_duty_cycle_ns(PWM_PERIOD)?; // Max the duty cycle, we want to start live.
-            pin.enable(true)?; // Go!
-        }
+    for pin in cfg.pins.values() {
+        pin.export()?; // Export if it isn't already
+        pin.set_period_ns(PWM_PERIOD)?; // Configure the period
+        pin.set_duty_cycle_ns(PWM_DEFAULT_DUTY_CYCLE)?; // Max the duty cycle, we want to start live.
+        pin.enable(true)?; // Go!
     }
 
     Ok(())
 }
 
-fn close_pins(cfg: &PwmLedConfig) -> Result<()> {
-    for (_, pins) in cfg.map.iter() {
-        for pin in pins {
-            pin.set_duty_cycle_ns(0)?; // Fully off so that next time it comes up, it must be configured.
-            pin.enable(false)?; // Disable the pin, reducing output to true zero.
-            pin.unexport()?; // Unexport.
-        }
+pub fn close_pins(mut cfg: PwmLedConfig) -> Result<()> {
+    cfg.open = false;
+    close_all(&cfg)
+}
+
+// Leaves the pins enabled at `fet` for good. If that fails they are closed on drop instead.
+pub fn keep_pins(mut cfg: PwmLedConfig, fet: &FetLevels) -> Result<()> {
+    update_pins(fet, &cfg)?;
+    cfg.open = false;
+    Ok(())
+}
+
+fn close_all(cfg: &PwmLedConfig) -> Result<()> {
+    for pin in cfg.pins.values() {
+        pin.set_duty_cycle_ns(0)?; // Fully off so that next time it comes up, it must be configured.
+        pin.enable(false)?; // Disable the pin, reducing output to true zero.
+        pin.unexport()?; // Unexport.
     }
 
     Ok(())
 }
 
-fn update_pins(
-    fet_packet: [u8; 3], // FET0: Chassis; FET1: Ring; FET2: Side
+pub fn update_pins(
+    fet: &FetLevels,
     cfg: &PwmLedConfig,
 ) -> Result<()> {
-    struct avgData {
-        count: u8,
+    #[derive(Default)]
+    struct AvgData {
+        count: u32,
         sum: u32,
     }
 
     // Create our averages
-    let mut outputs: HashMap<Pwm, avgData> = HashMap::new();
-
-    fn apply_packet(section: PwmLedSection, value: u8, cfg: &PwmLedConfig) {
-        match cfg.map.get(&section) {
-            Some(pins) => {
-                pins.iter().for_each(|x| {
-                    outputs[x].sum += value as u32;
-                    outputs[x].count += 1;
-                })
-            }
-            None => {} // no-op
+    let mut outputs: HashMap<PinId, AvgData> = HashMap::new();
+
+    let mut apply_packet = |section: PwmLedSection, value: u8| {
+        for pin in cfg.map.get(&section).into_iter().flatten() {
+            let avg = outputs.entry(*pin).or_default();
+            avg.sum += value as u32;
+            avg.count += 1;
         }
-    }
+    };
 
-    apply_packet(Chassis, fet_packet[0], cfg);
-    apply_packet(Ring, fet_packet[1], cfg);
-    apply_packet(Side, fet_packet[2], cfg);
+    apply_packet(Chassis, fet.chassis);
+    apply_packet(Ring, fet.ring);
+    apply_packet(Side, fet.side);
 
-    outputs.iter().for_each(|pwm, avg| {
-        let duty_cycle = PWM_SPEED_MAPPING[(avg.sum / avg.count) as u8]; // Average will always be [0,255], so this conversion is safe
-        pwm.set_duty_cycle_ns(duty_cycle); // Change LED juicing
-    });
+    for (pin, avg) in outputs {
+        let duty_cycle = PWM_SPEED_MAPPING[(avg.sum / avg.count) as usize]; // Average will always be [0,255], so this index is safe
+        cfg.pins[&pin].set_duty_cycle_ns(duty_cycle)?; // Change LED juicing
+    }
 
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,8 @@
+#[cfg(feature = "tokio")]
+pub mod jvs_codec;
+pub mod jvs_parser;
+pub mod led_board;
+pub mod sega_led;
+
+#[cfg(test)]
+mod test;
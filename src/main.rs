@@ -1,18 +1,35 @@
-mod jvs_parser;
+mod eeprom;
+mod emulate;
+mod fallback;
 mod proxy;
-mod sega_led;
+mod serial;
+mod supervisor;
+mod tee;
+mod led_pwm;
+mod watchdog;
 
 #[cfg(test)]
-mod test;
-mod led_pwm;
+mod bin_test;
 
-use crate::jvs_parser::JVSPacket;
+use crate::emulate::EmulateOptions;
+use crate::fallback::FallbackLighting;
+use crate::proxy::{PairSpec, ProxyOptions, ProxyPair};
+use crate::serial::SerialSettings;
+use crate::supervisor::FailurePolicy;
+use crate::tee::TeeTarget;
 use anyhow::Result;
+use mailight_rs::jvs_parser::{self, JVSPacket};
+use mailight_rs::sega_led::{self, BoardInfo, BoardStatus, ProtocolVersion};
 use std::fs::File;
-use std::io::{BufReader, Read};
+use std::io::{BufReader, Read, Write};
 use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Duration;
 use structopt::StructOpt;
 
+const SERIAL_HELP: &str = "Serial settings for the port. Format: comma-separated `<key>=<value>` out of baud, data (5-8), parity (none/odd/even), stop (1/2), flow (none/software/hardware), timeout (ms), dtr and rts (on/off), e.g. baud=38400,dtr=on. Defaults to 115200 8N1";
+
 #[derive(Debug, StructOpt)]
 enum Opts {
     File {
@@ -21,6 +38,10 @@ enum Opts {
     Proxy {
         alls_port: PathBuf,
         led_port: PathBuf,
+        #[structopt(long, help = SERIAL_HELP)]
+        alls_serial: Option<SerialSettings>,
+        #[structopt(long, help = SERIAL_HELP)]
+        led_serial: Option<SerialSettings>,
         #[structopt(short, long, help = "Fix RGB vs RBG mixup")]
         fix_rbg: bool,
         #[structopt(short, long, help = "Log packets as they are sent")]
@@ -33,6 +54,56 @@ enum Opts {
         side: Option<Vec<String>>,
         #[structopt(short, long, help="Format: `<pwmchip#>-<pwm#>`, e.g. 0-3, 1-4. Accepts multiple arguments. Overlaps between FET pins average.")]
         chassis: Option<Vec<String>>,
+
+        // board identity
+        #[structopt(long, default_value = "15070-04", help = "Part number to report to the game")]
+        board_part_number: String,
+        #[structopt(long, default_value = "1", help = "Firmware version to report to the game")]
+        board_firmware_version: u8,
+        #[structopt(long, help = "Pass the real board's info through instead of reporting our own")]
+        passthrough_board_info: bool,
+        #[structopt(long, help = "Answer protocol version queries locally. Format: `<major>.<minor>`, e.g. 1.4")]
+        protocol_version: Option<ProtocolVersion>,
+        #[structopt(long, help = "Answer board status queries locally. Format: `<timeout status>,<timeout sec>,<pwm io>,<fet timeout>`, e.g. 0,0,0,0")]
+        board_status: Option<BoardStatus>,
+        #[structopt(long, help = "Emulate the board's EEPROM with this file, created if missing")]
+        eeprom_file: Option<PathBuf>,
+
+        // watchdog
+        #[structopt(long, help = "Lighting to show when the game stops sending commands: off, dim, fade or idle")]
+        fallback: Option<FallbackLighting>,
+        #[structopt(long, default_value = "5", help = "Seconds without commands before falling back, until the game sets its own timeout")]
+        watchdog_timeout: u64,
+        #[structopt(long, default_value = "0", help = "Seconds between board status checks, to replay the game's state to a board that reset without reconnecting. Off (0) by default. Detection is a heuristic: it assumes the status's timeout field only changes when the game sets it or the board resets")]
+        status_poll_interval: u64,
+
+        #[structopt(long, default_value = "shutdown", help = "What to do when a proxy thread fails: restart it, or shutdown and exit with an error")]
+        on_failure: FailurePolicy,
+        #[structopt(long, default_value = "off", help = "Lighting to leave the board in on exit: off, dim, fade or idle. PWM pins are closed when it is off or fade, and left on at its level otherwise")]
+        final_lighting: FallbackLighting,
+        #[structopt(long, help = "Mirror everything sent to the LED board onto another board, discarding its replies. Accepts multiple arguments. Format: comma-separated `<key>=<value>` out of port, order (e.g. grb) and brightness (percent), e.g. port=/dev/ttyUSB4,order=rbg,brightness=50. Uses the LED board's serial settings")]
+        tee: Vec<TeeTarget>,
+
+        // more cabinet sides
+        #[structopt(long = "pair", help = "Proxy another ALLS and LED board pair alongside the first, which is 1P. Accepts multiple arguments. Format: comma-separated `<key>=<value>` out of name, alls, led, fix_rbg (on/off, or no value for on), ring, side, chassis, eeprom, tee_port, tee_order and tee_brightness (for the latest tee_port), and any serial setting prefixed with alls_ or led_, e.g. alls=/dev/ttyUSB2,led=/dev/ttyUSB3,ring=0-1,led_baud=38400,tee_port=/dev/ttyUSB5,tee_order=grb. Serial settings and fix_rbg start from the first pair's; PWM pins, eeprom and tees are the pair's own, none if left out; everything else is shared with the first pair")]
+        pairs: Vec<PairSpec>,
+    },
+    Emulate {
+        alls_port: PathBuf,
+        #[structopt(long, help = SERIAL_HELP)]
+        alls_serial: Option<SerialSettings>,
+        #[structopt(short, long, help = "Log packets as they are received")]
+        log_traffic: bool,
+        #[structopt(long, default_value = "15070-04", help = "Part number to report to the game")]
+        board_part_number: String,
+        #[structopt(long, default_value = "1", help = "Firmware version to report to the game")]
+        board_firmware_version: u8,
+        #[structopt(long, default_value = "1.0", help = "Protocol version to report. Format: `<major>.<minor>`")]
+        protocol_version: ProtocolVersion,
+        #[structopt(long, default_value = "0,0,0,0", help = "Board status to report. Format: `<timeout status>,<timeout sec>,<pwm io>,<fet timeout>`")]
+        board_status: BoardStatus,
+        #[structopt(long, help = "Keep the EEPROM in this file, created if missing. Starts blank every run otherwise")]
+        eeprom_file: Option<PathBuf>,
     },
 }
 
@@ -43,28 +114,87 @@ fn parse_file(path: PathBuf) -> Result<()> {
     let mut new_buf = Vec::new();
     reader.read_to_end(&mut buf)?;
     for byte in &buf {
-        if let Some(packet) = jvs.read_byte(*byte) {
-            tracing::info!(
-                "Got packet: src {} dst {} len {}",
-                packet.source_id,
-                packet.dest_id,
-                packet.payload.len()
-            );
-            match sega_led::LEDCommand::parse(packet) {
-                Ok(cmd) => {
-                    tracing::info!("LED command: {:?}", cmd);
-                    let mut new_pkt = JVSPacket::new(packet.source_id, packet.dest_id);
-                    cmd.serialize_to_jvs(&mut new_pkt);
-                    new_pkt.serialize(&mut new_buf);
+        match jvs.read_byte(*byte) {
+            Ok(Some(packet)) => {
+                tracing::info!(
+                    "Got packet: src {} dst {} len {}",
+                    packet.source_id,
+                    packet.dest_id,
+                    packet.payload.len()
+                );
+                match sega_led::LEDCommand::parse(&packet) {
+                    Ok(cmd) => {
+                        tracing::info!("LED command: {:?}", cmd);
+                        let mut new_pkt = JVSPacket::new(packet.source_id, packet.dest_id);
+                        cmd.serialize_to_jvs(&mut new_pkt);
+                        new_pkt.serialize(&mut new_buf)?;
+                    }
+                    Err(err) => tracing::error!("Couldn't parse: {:?}", err),
                 }
-                Err(err) => tracing::error!("Couldn't parse: {:?}", err),
             }
+            Ok(None) => (),
+            Err(err) => tracing::error!("Bad JVS data: {}", err),
         }
     }
     assert_eq!(buf, new_buf);
     Ok(())
 }
 
+fn with_overrides(
+    settings: &SerialSettings,
+    overrides: &[(String, String)],
+) -> Result<SerialSettings> {
+    let mut settings = settings.clone();
+    for (key, value) in overrides {
+        settings.set(key, value)?;
+    }
+    Ok(settings)
+}
+
+// The first pair takes `options` as given; the rest override them with their own spec.
+fn build_pairs(
+    options: ProxyOptions,
+    first: PairSpec,
+    extra: Vec<PairSpec>,
+) -> Result<Vec<ProxyPair>> {
+    let mut pairs = vec![ProxyPair {
+        pwm: led_pwm::create_config(first.ring, first.side, first.chassis)?,
+        alls_port: first.alls_port,
+        led_port: first.led_port,
+        options: options.clone(),
+    }];
+    for (index, spec) in extra.into_iter().enumerate() {
+        pairs.push(ProxyPair {
+            pwm: led_pwm::create_config(spec.ring, spec.side, spec.chassis)?,
+            alls_port: spec.alls_port,
+            led_port: spec.led_port,
+            options: ProxyOptions {
+                name: spec.name.unwrap_or_else(|| format!("{}P", index + 2)),
+                alls_serial: with_overrides(&options.alls_serial, &spec.alls_serial)?,
+                led_serial: with_overrides(&options.led_serial, &spec.led_serial)?,
+                fix_rbg: spec.fix_rbg.unwrap_or(options.fix_rbg),
+                // Each board has its own EEPROM and tee ports, so these never carry over.
+                eeprom_file: spec.eeprom_file,
+                tee: spec.tee,
+                ..options.clone()
+            },
+        });
+    }
+    Ok(pairs)
+}
+
+// The first signal asks everything to wind down; a second one exits on the spot. Only for
+// subcommands that watch the flag, so the rest still stop on the first Ctrl-C.
+fn install_shutdown_handlers() -> Arc<AtomicBool> {
+    let shutdown = Arc::new(AtomicBool::new(false));
+    for signal in [signal_hook::consts::SIGINT, signal_hook::consts::SIGTERM] {
+        signal_hook::flag::register_conditional_shutdown(signal, 1, shutdown.clone())
+            .and_then(|_| signal_hook::flag::register(signal, shutdown.clone()))
+            .expect("installing signal handlers failed");
+    }
+    shutdown
+}
+
 fn main() {
     let subscriber = tracing_subscriber::FmtSubscriber::builder()
         .with_max_level(tracing::Level::INFO)
@@ -78,16 +208,84 @@ fn main() {
         Opts::Proxy {
             alls_port,
             led_port,
+            alls_serial,
+            led_serial,
             fix_rbg,
             log_traffic,
-            pwm_chip,
             ring,
             side,
             chassis,
-        } => crate::proxy::proxy(alls_port, led_port, fix_rbg, log_traffic,
-                                 led_pwm::create_config(ring, side, chassis, pwm_chip)),
+            board_part_number,
+            board_firmware_version,
+            passthrough_board_info,
+            protocol_version,
+            board_status,
+            eeprom_file,
+            fallback,
+            watchdog_timeout,
+            status_poll_interval,
+            on_failure,
+            final_lighting,
+            tee,
+            pairs,
+        } => {
+            let options = ProxyOptions {
+                name: "1P".to_owned(),
+                alls_serial: alls_serial.unwrap_or_default(),
+                led_serial: led_serial.unwrap_or_default(),
+                fix_rbg,
+                log_traffic,
+                board_info: (!passthrough_board_info)
+                    .then(|| BoardInfo::new(&board_part_number, board_firmware_version)),
+                protocol_version,
+                board_status,
+                eeprom_file,
+                fallback,
+                watchdog_timeout: Duration::from_secs(watchdog_timeout),
+                status_poll_interval: (status_poll_interval != 0)
+                    .then(|| Duration::from_secs(status_poll_interval)),
+                on_failure,
+                final_lighting,
+                tee,
+            };
+            let first = PairSpec {
+                alls_port,
+                led_port,
+                ring,
+                side,
+                chassis,
+                ..Default::default()
+            };
+            let shutdown = install_shutdown_handlers();
+            build_pairs(options, first, pairs)
+                .and_then(|pairs| crate::proxy::proxy_all(pairs, &shutdown))
+        }
+        Opts::Emulate {
+            alls_port,
+            alls_serial,
+            log_traffic,
+            board_part_number,
+            board_firmware_version,
+            protocol_version,
+            board_status,
+            eeprom_file,
+        } => {
+            let options = EmulateOptions {
+                alls_serial: alls_serial.unwrap_or_default(),
+                log_traffic,
+                board_info: BoardInfo::new(&board_part_number, board_firmware_version),
+                protocol_version,
+                board_status,
+                eeprom_file,
+            };
+            let shutdown = install_shutdown_handlers();
+            crate::emulate::emulate(alls_port, options, &shutdown)
+        }
     };
     if let Err(err) = result {
         tracing::error!("Error: {:?}", err);
+        let _ = std::io::stdout().flush();
+        std::process::exit(1);
     }
+    let _ = std::io::stdout().flush();
 }
@@ -1,45 +1,120 @@
-use crate::jvs_parser::{JVSPacket, SegaJVSReader};
-use crate::sega_led::LEDCommand;
-use anyhow::Result;
-use std::io::{BufReader, Read};
+use crate::eeprom::EepromFile;
+use crate::fallback::FallbackLighting;
+use crate::led_pwm::{self, PwmLedConfig};
+use crate::serial::{self, ReconnectingPort, SerialSettings};
+use crate::supervisor::{supervise, FailurePolicy, SetOnDrop};
+use crate::tee::{Tee, TeeTarget};
+use crate::watchdog::Watchdog;
+use anyhow::{anyhow, bail, Context, Result};
+use mailight_rs::jvs_parser::{JVSPacket, SegaJVSReader};
+use mailight_rs::led_board::VirtualLedBoard;
+use mailight_rs::sega_led::{
+    BoardInfo, BoardStatus, FetLevels, LEDCommand, LEDCommandType, LEDReply, LEDReplyBody,
+    ProtocolVersion,
+};
+use std::collections::{HashSet, VecDeque};
 use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Mutex;
-use std::time::Duration;
-
-const BAUD_RATE: u32 = 115200;
-const BOARD_INFO: &str = "15070-04";
-
-fn read_and_retry(reader: &mut impl Read, buf: &mut [u8]) -> std::io::Result<()> {
-    loop {
-        match reader.read_exact(buf) {
-            Err(err) => {
-                if err.kind() != std::io::ErrorKind::TimedOut {
-                    return Err(err);
-                }
-            }
-            Ok(_) => return Ok(()),
-        };
-    }
+use std::thread::ScopedJoinHandle;
+use std::time::{Duration, Instant};
+
+pub const READ_BUFFER_SIZE: usize = 1024;
+const WATCHDOG_POLL_INTERVAL: Duration = Duration::from_millis(250);
+// Slack on top of the time injected commands and their replies take on the wire.
+const INJECTED_REPLY_MARGIN: Duration = Duration::from_secs(1);
+// Line bits per byte with every start, parity and stop bit the settings allow.
+const BITS_PER_BYTE: u64 = 12;
+// Sync, addresses, length and checksum around each payload.
+const JVS_FRAME_BYTES: u64 = 5;
+// Room for the longest reply, a board info string.
+const MAX_REPLY_BYTES: u64 = 32;
+
+#[derive(Clone)]
+pub struct ProxyOptions {
+    /// Which cabinet side this is, to tell the logs of several proxies apart.
+    pub name: String,
+    pub alls_serial: SerialSettings,
+    pub led_serial: SerialSettings,
+    pub fix_rbg: bool,
+    pub log_traffic: bool,
+    /// Answer these queries locally instead of asking the board. `None` passes them through.
+    pub board_info: Option<BoardInfo>,
+    pub protocol_version: Option<ProtocolVersion>,
+    pub board_status: Option<BoardStatus>,
+    /// Serve EEPROM reads and writes from this file instead of the board.
+    pub eeprom_file: Option<PathBuf>,
+    /// Lighting to fall back to when the game goes quiet. `None` disables the watchdog.
+    pub fallback: Option<FallbackLighting>,
+    /// Watchdog timeout until the game sends its own `SetTimeout`.
+    pub watchdog_timeout: Duration,
+    pub on_failure: FailurePolicy,
+    /// Lighting to leave the board in when the proxy stops. PWM pins are closed if it is off,
+    /// and left enabled at its FET levels otherwise.
+    pub final_lighting: FallbackLighting,
+    /// How often to ask the board for its status, to guess when it reset without dropping off
+    /// USB. `None` only notices resets that reconnect.
+    pub status_poll_interval: Option<Duration>,
+    /// Extra boards to mirror the LED board onto. They get exactly what it gets, `fix_rbg`
+    /// included, before their own channel order and brightness.
+    pub tee: Vec<TeeTarget>,
+}
+
+fn local_reply(jvs_request: &JVSPacket, command: LEDCommandType, body: LEDReplyBody) -> JVSPacket {
+    let mut response = jvs_request.reply();
+    LEDReply::new(command, body).serialize_to_jvs(&mut response);
+    response
 }
 
 fn mitm_packet(
     jvs_request: &JVSPacket,
     request_to_led: &mut LEDCommand,
-    fix_rbg: bool,
+    options: &ProxyOptions,
+    eeprom: Option<&mut EepromFile>,
 ) -> Option<JVSPacket> {
     match request_to_led {
-        LEDCommand::GetBoardInfoCommand(ref mut buf) => {
-            buf.clear();
-            buf.extend_from_slice(BOARD_INFO.as_bytes());
-            buf.push(255);
-            buf.push(1);
-            let mut response = JVSPacket {
-                source_id: jvs_request.dest_id,
-                dest_id: jvs_request.source_id,
-                ..Default::default()
-            };
-            request_to_led.serialize_reply_to_jvs(&mut response);
-            return Some(response);
+        LEDCommand::GetBoardInfoCommand(_) => {
+            let info = options.board_info.as_ref()?;
+            return Some(local_reply(
+                jvs_request,
+                LEDCommandType::GetBoardInfoCommand,
+                LEDReplyBody::BoardInfo(info.clone()),
+            ));
+        }
+        LEDCommand::GetProtocolVersionCommand(_) => {
+            let version = options.protocol_version?;
+            return Some(local_reply(
+                jvs_request,
+                LEDCommandType::GetProtocolVersionCommand,
+                LEDReplyBody::ProtocolVersion(version),
+            ));
+        }
+        LEDCommand::GetBoardStatusCommand(_) => {
+            let status = options.board_status?;
+            return Some(local_reply(
+                jvs_request,
+                LEDCommandType::GetBoardStatusCommand,
+                LEDReplyBody::BoardStatus(status),
+            ));
+        }
+        LEDCommand::EepromRead { address } => {
+            let data = eeprom?.read(*address);
+            return Some(local_reply(
+                jvs_request,
+                LEDCommandType::EepromRead,
+                LEDReplyBody::EepromRead(data),
+            ));
+        }
+        LEDCommand::EepromWrite { address, data } => {
+            if let Err(err) = eeprom?.write(*address, *data) {
+                tracing::error!("Couldn't save EEPROM write to {:#04x}: {:?}", address, err);
+            }
+            return Some(local_reply(
+                jvs_request,
+                LEDCommandType::EepromWrite,
+                LEDReplyBody::Ack,
+            ));
         }
         LEDCommand::SetLED {
             ref mut g,
@@ -55,90 +130,605 @@ fn mitm_packet(
             ref mut g,
             ref mut b,
             ..
-        } => {
-            if fix_rbg {
-                std::mem::swap(b, g);
-            }
-        }
+        } if options.fix_rbg => std::mem::swap(b, g),
         _ => (),
     };
     None
 }
 
-pub fn proxy(
-    alls_port: PathBuf,
-    led_port: PathBuf,
-    fix_rbg: bool,
-    log_traffic: bool,
-) -> Result<()> {
-    let mut alls_reader_port = serialport::new(alls_port.to_str().unwrap(), BAUD_RATE)
-        .timeout(Duration::from_secs(1))
-        .open()?;
-    let alls_writer = Mutex::new(alls_reader_port.try_clone()?);
-    let mut alls_reader = BufReader::new(&mut alls_reader_port);
-
-    let mut led_reader_port = serialport::new(led_port.to_str().unwrap(), BAUD_RATE)
-        .timeout(Duration::from_secs(1))
-        .open()?;
-    let mut led_writer = led_reader_port.try_clone()?;
-    let mut led_reader = BufReader::new(&mut led_reader_port);
+/// Frames `(source_id, dest_id, command)`s for an LED board.
+pub fn frame_commands(commands: &[(u8, u8, LEDCommand)], buf: &mut Vec<u8>) -> Result<()> {
+    for (source_id, dest_id, cmd) in commands {
+        let mut packet = JVSPacket::new(*source_id, *dest_id);
+        cmd.serialize_to_jvs(&mut packet);
+        packet.serialize(buf)?;
+    }
+    Ok(())
+}
+
+/// What a reset board needs to pick up where it left off.
+fn replay_commands(
+    board: &VirtualLedBoard,
+    timeout: Option<u16>,
+    source_id: u8,
+    dest_id: u8,
+) -> Vec<(u8, u8, LEDCommand)> {
+    let timeout = timeout.map(|seconds| LEDCommand::SetTimeout { seconds });
+    timeout
+        .into_iter()
+        .chain(board.replay_commands())
+        .map(|cmd| (source_id, dest_id, cmd))
+        .collect()
+}
+
+/// Replies to commands the proxy sent the board on its own, which the game never asked for.
+///
+/// Each expected reply is matched by opcode and given up on after a while, so a board that
+/// drops one never ends up eating the game's replies instead.
+pub struct InjectedReplies {
+    baud_rate: u32,
+    pending: Mutex<VecDeque<(u8, Instant)>>,
+}
+impl InjectedReplies {
+    /// For a board talking at `baud_rate`, which sets how long its replies can take.
+    pub fn new(baud_rate: u32) -> Self {
+        InjectedReplies {
+            baud_rate,
+            pending: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// How long the board can take to answer all of `commands`: the time to send them and
+    /// get the replies back, assuming every byte needs escaping.
+    fn window(&self, commands: &[(u8, u8, LEDCommand)]) -> Duration {
+        let mut payload = Vec::new();
+        let wire_bytes: u64 = commands
+            .iter()
+            .map(|(_, _, cmd)| {
+                payload.clear();
+                cmd.serialize(&mut payload);
+                2 * (payload.len() as u64 + MAX_REPLY_BYTES + 2 * JVS_FRAME_BYTES)
+            })
+            .sum();
+        let micros = wire_bytes * BITS_PER_BYTE * 1_000_000 / self.baud_rate.max(1) as u64;
+        INJECTED_REPLY_MARGIN + Duration::from_micros(micros)
+    }
+
+    /// Expects a reply to each of `commands`, about to be sent at `now`.
+    pub fn expect(&self, commands: &[(u8, u8, LEDCommand)], now: Instant) {
+        let deadline = now + self.window(commands);
+        let mut pending = self.pending.lock().unwrap();
+        pending.extend(commands.iter().map(|(_, _, cmd)| (cmd.opcode(), deadline)));
+    }
+
+    /// Whether a reply to `opcode`, arriving at `now`, answers one of the proxy's commands,
+    /// forgetting that command.
+    pub fn take(&self, opcode: u8, now: Instant) -> bool {
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|(_, deadline)| *deadline > now);
+        match pending.iter().position(|(expected, _)| *expected == opcode) {
+            Some(index) => {
+                pending.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Spots a board that reset without dropping off USB, which shows as its status changing when
+/// nothing was sent to change it.
+///
+/// This is a guess: it takes the timeout a status reports to be the one last set, which a reset
+/// puts back to the board's default. A board reporting anything else there, a countdown say,
+/// would look like it resets on every poll.
+#[derive(Default)]
+struct ResetDetector {
+    status: Mutex<Option<BoardStatus>>,
+    reset: AtomicBool,
+}
+impl ResetDetector {
+    /// Forgets the board's last status, after sending it something that changes it.
+    fn rebase(&self) {
+        *self.status.lock().unwrap() = None;
+    }
+
+    fn check(&self, status: &BoardStatus) {
+        let mut last = self.status.lock().unwrap();
+        match *last {
+            Some(last) if last.timeout_sec != status.timeout_sec => {
+                tracing::warn!(
+                    "LED board timeout went from {}s to {}s on its own, it must have reset",
+                    last.timeout_sec,
+                    status.timeout_sec
+                );
+                self.reset.store(true, Ordering::SeqCst);
+            }
+            _ => *last = Some(*status),
+        }
+    }
+
+    /// Whether the board reset since this was last called.
+    fn take_reset(&self) -> bool {
+        self.reset.swap(false, Ordering::SeqCst)
+    }
+}
+
+/// One ALLS to LED board connection, with everything that can differ between cabinet sides.
+pub struct ProxyPair {
+    pub alls_port: PathBuf,
+    pub led_port: PathBuf,
+    pub options: ProxyOptions,
+    pub pwm: PwmLedConfig,
+}
+
+/// Per-side overrides for an extra `ProxyPair`, parsed from comma-separated `key=value` pairs,
+/// e.g. `alls=/dev/ttyUSB2,led=/dev/ttyUSB3,fix_rbg,ring=0-1,led_baud=38400`. `ring`, `side` and
+/// `chassis` can repeat. Serial settings prefixed with `alls_` or `led_` apply on top of the
+/// first pair's, and tee settings are prefixed with `tee_`.
+#[derive(Debug, Default)]
+pub struct PairSpec {
+    pub name: Option<String>,
+    pub alls_port: PathBuf,
+    pub led_port: PathBuf,
+    /// `None` keeps the first pair's.
+    pub fix_rbg: Option<bool>,
+    pub ring: Option<Vec<String>>,
+    pub side: Option<Vec<String>>,
+    pub chassis: Option<Vec<String>>,
+    pub eeprom_file: Option<PathBuf>,
+    /// `(key, value)` serial settings, in the order given.
+    pub alls_serial: Vec<(String, String)>,
+    pub led_serial: Vec<(String, String)>,
+    /// Each `tee_port` starts a tee; `tee_order` and `tee_brightness` apply to the latest one.
+    pub tee: Vec<TeeTarget>,
+}
+impl FromStr for PairSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut spec = PairSpec::default();
+        serial::for_each_setting(s, |key, value| {
+            match key {
+                "name" => spec.name = Some(value.to_owned()),
+                "alls" => spec.alls_port = value.into(),
+                "led" => spec.led_port = value.into(),
+                "fix_rbg" if value.is_empty() => spec.fix_rbg = Some(true),
+                "fix_rbg" => spec.fix_rbg = Some(serial::parse_level(value)?),
+                "ring" => spec.ring.get_or_insert_with(Vec::new).push(value.to_owned()),
+                "side" => spec.side.get_or_insert_with(Vec::new).push(value.to_owned()),
+                "chassis" => spec.chassis.get_or_insert_with(Vec::new).push(value.to_owned()),
+                "eeprom" => spec.eeprom_file = Some(value.into()),
+                "tee_port" => spec.tee.push(format!("port={}", value).parse()?),
+                _ => match key.split_once('_') {
+                    Some(("alls", setting)) => push_serial(&mut spec.alls_serial, setting, value)?,
+                    Some(("led", setting)) => push_serial(&mut spec.led_serial, setting, value)?,
+                    Some(("tee", setting)) => match spec.tee.last_mut() {
+                        Some(tee) => tee.set(setting, value)?,
+                        None => bail!("`{}` needs a `tee_port` before it", key),
+                    },
+                    _ => bail!(
+                        "Unknown pair setting `{}`, expected name, alls, led, fix_rbg, ring, side, chassis, eeprom, tee_ settings or alls_/led_ serial settings",
+                        key
+                    ),
+                },
+            }
+            Ok(())
+        })?;
+        if spec.alls_port.as_os_str().is_empty() || spec.led_port.as_os_str().is_empty() {
+            bail!("A pair needs both `alls=<port>` and `led=<port>`");
+        }
+        Ok(spec)
+    }
+}
+
+// Checked straight away, so a typo fails at startup rather than when the pair opens.
+fn push_serial(overrides: &mut Vec<(String, String)>, key: &str, value: &str) -> Result<()> {
+    SerialSettings::default().set(key, value)?;
+    overrides.push((key.to_owned(), value.to_owned()));
+    Ok(())
+}
 
+// Supervised threads catch their own panics; any other is reported rather than passed on.
+fn join_thread(thread: ScopedJoinHandle<Result<()>>) -> Result<()> {
+    thread
+        .join()
+        .unwrap_or_else(|_| Err(anyhow!("A proxy thread panicked")))
+}
+
+/// Runs every pair side by side until `stop` is raised or one of them gives up, which stops the
+/// rest too.
+pub fn proxy_all(pairs: Vec<ProxyPair>, stop: &AtomicBool) -> Result<()> {
     std::thread::scope(|scope| {
+        let threads: Vec<_> = pairs
+            .into_iter()
+            .map(|pair| {
+                scope.spawn(move || {
+                    let _exit = SetOnDrop(stop);
+                    let name = pair.options.name.clone();
+                    proxy(pair, stop).with_context(|| format!("{} proxy failed", name))
+                })
+            })
+            .collect();
+        // Join every pair, so each one gets to tidy up, before reporting the first failure.
+        let results: Vec<_> = threads.into_iter().map(join_thread).collect();
+        results.into_iter().collect()
+    })
+}
+
+/// `lighting`'s commands at `step`, addressed like the game's last command.
+fn fallback_commands(
+    lighting: FallbackLighting,
+    step: u32,
+    source_id: u8,
+    dest_id: u8,
+) -> Vec<(u8, u8, LEDCommand)> {
+    lighting
+        .commands(step)
+        .into_iter()
+        .map(|cmd| (source_id, dest_id, cmd))
+        .collect()
+}
+
+pub fn proxy(pair: ProxyPair, stop: &AtomicBool) -> Result<()> {
+    let ProxyPair {
+        alls_port,
+        led_port,
+        options,
+        pwm,
+    } = pair;
+    // Any early return drops `pwm`, which closes its pins.
+    let span = tracing::info_span!("proxy", side = %options.name);
+    let _span = span.enter();
+    let alls = ReconnectingPort::open("ALLS", alls_port, options.alls_serial.clone())?;
+    let led = ReconnectingPort::open("LED board", led_port, options.led_serial.clone())?;
+    let tees = options
+        .tee
+        .iter()
+        .map(|target| Tee::open(target.clone(), options.led_serial.clone()))
+        .collect::<Vec<_>>();
+
+    let mut eeprom = options
+        .eeprom_file
+        .as_deref()
+        .map(EepromFile::open)
+        .transpose()?;
+
+    let watchdog = Watchdog::new(options.watchdog_timeout);
+    let injected = InjectedReplies::new(options.led_serial.baud_rate);
+    let reset_detector = ResetDetector::default();
+
+    let result = std::thread::scope(|scope| {
+        let mut threads = Vec::new();
+
+        for tee in &tees {
+            let span = &span;
+            let on_failure = options.on_failure;
+            threads.push(scope.spawn(move || {
+                let _span = span.enter();
+                supervise("LED tee", on_failure, stop, || -> Result<()> {
+                    tee.drain(stop);
+                    Ok(())
+                })
+            }));
+        }
+
         // Passthrough responses from the LED board.
-        scope.spawn(|| -> Result<()> {
-            let mut jvs_reader = SegaJVSReader::default();
-            let mut buf = [0u8; 1];
-            loop {
-                read_and_retry(&mut led_reader, &mut buf)?;
-                if let Some(packet) = jvs_reader.read_byte(buf[0]) {
-                    let mut buffer = Vec::new();
-                    packet.serialize(&mut buffer);
-                    alls_writer.lock().unwrap().write_all(&buffer)?;
-                }
-            }
-        });
+        threads.push(scope.spawn(|| {
+            let _span = span.enter();
+            supervise(
+                "LED board to ALLS",
+                options.on_failure,
+                stop,
+                || -> Result<()> {
+                    let mut jvs_reader = SegaJVSReader::default();
+                    let mut buf = [0u8; READ_BUFFER_SIZE];
+                    let mut send_buffer = Vec::new();
+                    while let Some(len) = led.read(&mut buf, stop) {
+                        let errors_before = *jvs_reader.error_counts();
+                        send_buffer.clear();
+                        for result in jvs_reader.feed(&buf[..len]) {
+                            let packet = match result {
+                                Ok(packet) => packet,
+                                Err(err) => {
+                                    tracing::error!("Bad JVS data from LED board: {}", err);
+                                    continue;
+                                }
+                            };
+                            let reply = LEDReply::parse(&packet);
+                            if let Ok(LEDReply {
+                                body: LEDReplyBody::BoardStatus(status),
+                                ..
+                            }) = &reply
+                            {
+                                reset_detector.check(status);
+                            }
+                            // The opcode sits after the status byte.
+                            if let Some(opcode) = packet.payload.get(1) {
+                                if injected.take(*opcode, Instant::now()) {
+                                    continue;
+                                }
+                            }
+                            match reply {
+                                Ok(reply) => {
+                                    if options.log_traffic {
+                                        tracing::info!("LED reply: {:?}", reply);
+                                    }
+                                    match &reply.body {
+                                        LEDReplyBody::BoardInfo(info) => {
+                                            tracing::info!("LED board reports itself as {}", info)
+                                        }
+                                        LEDReplyBody::ProtocolVersion(version) => {
+                                            tracing::info!("LED board speaks protocol {}", version)
+                                        }
+                                        LEDReplyBody::BoardStatus(status) => {
+                                            tracing::info!("LED board status: {:?}", status)
+                                        }
+                                        _ => (),
+                                    }
+                                    if !reply.is_ok() {
+                                        tracing::warn!(
+                                            "LED board rejected {:#04x}: status {:?} report {:?}",
+                                            reply.opcode,
+                                            reply.status,
+                                            reply.report
+                                        );
+                                    }
+                                }
+                                Err(err) => tracing::warn!("Couldn't parse LED reply: {:?}", err),
+                            }
+                            packet.serialize(&mut send_buffer)?;
+                        }
+                        if !send_buffer.is_empty() {
+                            alls.write_all(&send_buffer);
+                        }
+                        if *jvs_reader.error_counts() != errors_before {
+                            tracing::warn!(
+                                "LED board JVS errors so far: {:?}",
+                                jvs_reader.error_counts()
+                            );
+                        }
+                    }
+                    Ok(())
+                },
+            )
+        }));
 
         // Read from the ALLS and proxy to the LED board.
-        scope.spawn(|| -> Result<()> {
-            let mut buf = [0u8; 1];
-            let mut jvs_reader = SegaJVSReader::default();
-            let mut send_buffer = Vec::new();
-            loop {
-                read_and_retry(&mut alls_reader, &mut buf)?;
-                if let Some(packet) = jvs_reader.read_byte(buf[0]) {
-                    if log_traffic {
-                        tracing::info!(
-                            "Got packet: src {} dst {} len {}",
-                            packet.source_id,
-                            packet.dest_id,
-                            packet.payload.len()
-                        );
-                    }
-                    match LEDCommand::parse(packet) {
-                        Ok(mut cmd) => {
-                            if log_traffic {
-                                tracing::info!("LED command: {:?}", cmd);
+        threads.push(scope.spawn(|| {
+            let _span = span.enter();
+            supervise(
+                "ALLS to LED board",
+                options.on_failure,
+                stop,
+                || -> Result<()> {
+                    let mut buf = [0u8; READ_BUFFER_SIZE];
+                    let mut jvs_reader = SegaJVSReader::default();
+                    let mut send_buffer = Vec::new();
+                    let mut reply_buffer = Vec::new();
+                    let mut unknown_opcodes = HashSet::new();
+                    // What the board should be showing, to restore it after a reconnect.
+                    let mut board_state = VirtualLedBoard::new();
+                    let mut timeout = None;
+                    let mut last_ids = None;
+                    let mut led_reconnects = led.reconnects();
+                    let mut last_status_poll = Instant::now();
+                    let mut tee_reconnects: Vec<_> =
+                        tees.iter().map(|tee| tee.port.reconnects()).collect();
+                    let mut forwarded = Vec::new();
+                    while let Some(len) = alls.read(&mut buf, stop) {
+                        let errors_before = *jvs_reader.error_counts();
+                        send_buffer.clear();
+                        reply_buffer.clear();
+                        forwarded.clear();
+                        for result in jvs_reader.feed(&buf[..len]) {
+                            let mut packet = match result {
+                                Ok(packet) => packet,
+                                Err(err) => {
+                                    tracing::error!("Bad JVS data from ALLS: {}", err);
+                                    continue;
+                                }
+                            };
+                            watchdog.feed(packet.source_id, packet.dest_id, Instant::now());
+                            if options.log_traffic {
+                                tracing::info!(
+                                    "Got packet: src {} dst {} len {}",
+                                    packet.source_id,
+                                    packet.dest_id,
+                                    packet.payload.len()
+                                );
                             }
-                            if let Some(mut override_response) =
-                                mitm_packet(packet, &mut cmd, fix_rbg)
-                            {
-                                send_buffer.clear();
-                                override_response.serialize(&mut send_buffer);
-                                alls_writer.lock().unwrap().write_all(&send_buffer)?;
-                                continue;
+                            match LEDCommand::parse(&packet) {
+                                Ok(mut cmd) => {
+                                    if options.log_traffic {
+                                        tracing::info!("LED command: {:?}", cmd);
+                                    }
+                                    match &cmd {
+                                        LEDCommand::SetFet(fet) => {
+                                            if let Err(err) = led_pwm::update_pins(fet, &pwm) {
+                                                tracing::error!(
+                                                    "Couldn't update PWM pins: {:?}",
+                                                    err
+                                                );
+                                            }
+                                        }
+                                        LEDCommand::SetTimeout { seconds } => {
+                                            watchdog.set_timeout(*seconds);
+                                            timeout = Some(*seconds);
+                                            reset_detector.rebase();
+                                        }
+                                        LEDCommand::Unknown { opcode, .. }
+                                            if unknown_opcodes.insert(*opcode) =>
+                                        {
+                                            tracing::warn!(
+                                                "Passing through unknown LED command {:#04x}",
+                                                opcode
+                                            )
+                                        }
+                                        _ => (),
+                                    }
+                                    if let Some(override_response) =
+                                        mitm_packet(&packet, &mut cmd, &options, eeprom.as_mut())
+                                    {
+                                        override_response.serialize(&mut reply_buffer)?;
+                                        continue;
+                                    }
+                                    cmd.serialize_to_jvs(&mut packet);
+                                    board_state.apply(&cmd);
+                                    last_ids = Some((packet.source_id, packet.dest_id));
+                                }
+                                Err(err) => {
+                                    tracing::error!("Couldn't parse: {:?}", err);
+                                }
+                            };
+                            packet.serialize(&mut send_buffer)?;
+                            if !tees.is_empty() {
+                                forwarded.push(packet);
                             }
-                            cmd.serialize_to_jvs(packet);
                         }
-                        Err(err) => {
-                            tracing::error!("Couldn't parse: {:?}", err);
+                        if !reply_buffer.is_empty() {
+                            alls.write_all(&reply_buffer);
                         }
-                    };
-                    send_buffer.clear();
-                    packet.serialize(&mut send_buffer);
-                    led_writer.write_all(&send_buffer)?;
-                }
-            }
-        });
+                        let reset = reset_detector.take_reset();
+                        if led.reconnects() != led_reconnects || reset {
+                            led_reconnects = led.reconnects();
+                            if let Some((source_id, dest_id)) = last_ids {
+                                let replay =
+                                    replay_commands(&board_state, timeout, source_id, dest_id);
+                                let mut replay_buffer = Vec::new();
+                                frame_commands(&replay, &mut replay_buffer)?;
+                                tracing::info!(
+                                    "Replaying {} commands to the {} LED board",
+                                    replay.len(),
+                                    if reset { "reset" } else { "reconnected" }
+                                );
+                                injected.expect(&replay, Instant::now());
+                                reset_detector.rebase();
+                                led.write_all(&replay_buffer);
+                            }
+                        }
+                        // Only polled while the game talks, so the board's own timeout still
+                        // trips once it stops.
+                        if let (Some(interval), Some((source_id, dest_id))) =
+                            (options.status_poll_interval, last_ids)
+                        {
+                            if last_status_poll.elapsed() >= interval {
+                                last_status_poll = Instant::now();
+                                let poll = [(
+                                    source_id,
+                                    dest_id,
+                                    LEDCommand::GetBoardStatusCommand(Vec::new()),
+                                )];
+                                frame_commands(&poll, &mut send_buffer)?;
+                                injected.expect(&poll, Instant::now());
+                            }
+                        }
+                        if !send_buffer.is_empty() {
+                            led.write_all(&send_buffer);
+                        }
+                        for (tee, seen) in tees.iter().zip(&mut tee_reconnects) {
+                            if tee.port.reconnects() != *seen {
+                                *seen = tee.port.reconnects();
+                                if let Some((source_id, dest_id)) = last_ids {
+                                    tee.send_commands(&replay_commands(
+                                        &board_state,
+                                        timeout,
+                                        source_id,
+                                        dest_id,
+                                    ))?;
+                                }
+                            }
+                            tee.send(&forwarded)?;
+                        }
+                        if *jvs_reader.error_counts() != errors_before {
+                            tracing::warn!(
+                                "ALLS JVS errors so far: {:?}",
+                                jvs_reader.error_counts()
+                            );
+                        }
+                    }
+                    Ok(())
+                },
+            )
+        }));
+
+        // Drive fallback lighting while the game is quiet.
+        if let Some(fallback) = options.fallback {
+            let (watchdog, injected, stop, led, tees, pwm) =
+                (&watchdog, &injected, stop, &led, &tees, &pwm);
+            let on_failure = options.on_failure;
+            let span = &span;
+            threads.push(scope.spawn(move || {
+                let _span = span.enter();
+                supervise("Fallback lighting", on_failure, stop, || -> Result<()> {
+                    let mut step: Option<u32> = None;
+                    let mut last_sent = Instant::now();
+                    let mut send_buffer = Vec::new();
+                    while !stop.load(Ordering::SeqCst) {
+                        std::thread::sleep(WATCHDOG_POLL_INTERVAL);
+                        let Some((source_id, dest_id)) = watchdog.expired(Instant::now()) else {
+                            if step.take().is_some() {
+                                tracing::info!("Game is talking again, leaving fallback lighting");
+                            }
+                            continue;
+                        };
+                        let next_step = match step {
+                            None => {
+                                tracing::warn!(
+                                    "No commands from the game in {:?}, switching to {:?} lighting",
+                                    watchdog.timeout(),
+                                    fallback
+                                );
+                                0
+                            }
+                            Some(step) => match fallback.refresh_interval() {
+                                Some(interval) if last_sent.elapsed() >= interval => step + 1,
+                                _ => continue,
+                            },
+                        };
+                        let commands = fallback_commands(fallback, next_step, source_id, dest_id);
+                        send_buffer.clear();
+                        frame_commands(&commands, &mut send_buffer)?;
+                        injected.expect(&commands, Instant::now());
+                        led.write_all(&send_buffer);
+                        tees.iter()
+                            .try_for_each(|tee| tee.send_commands(&commands))?;
+                        if let Err(err) = led_pwm::update_pins(&fallback.fet_levels(), pwm) {
+                            tracing::error!("Couldn't update PWM pins: {:?}", err);
+                        }
+                        last_sent = Instant::now();
+                        step = Some(next_step);
+                    }
+                    Ok(())
+                })
+            }));
+        }
+
+        // Join every thread, so a panic in one doesn't skip the teardown below.
+        let results: Vec<_> = threads.into_iter().map(join_thread).collect();
+        results.into_iter().collect::<Result<()>>()
     });
-    Ok(())
+
+    // Leave the cabinet in a known state rather than whatever the last frame was.
+    match watchdog.last_ids() {
+        Some((source_id, dest_id)) => {
+            tracing::info!("Leaving the LED board {:?}", options.final_lighting);
+            let commands = fallback_commands(options.final_lighting, 0, source_id, dest_id);
+            let mut send_buffer = Vec::new();
+            frame_commands(&commands, &mut send_buffer)?;
+            led.write_all(&send_buffer);
+            tees.iter()
+                .try_for_each(|tee| tee.send_commands(&commands))?;
+        }
+        None => tracing::info!("The game never spoke to the LED board, not sending a final frame"),
+    }
+    // Closing the pins zeroes them, so only lighting that is off anyway gets to close them.
+    let final_fet = options.final_lighting.fet_levels();
+    if final_fet == FetLevels::default() {
+        led_pwm::close_pins(pwm)?;
+    } else {
+        tracing::info!("Leaving the PWM pins enabled at {:?}", final_fet);
+        led_pwm::keep_pins(pwm, &final_fet)?;
+    }
+    result
 }
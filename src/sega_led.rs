@@ -1,6 +1,6 @@
 use crate::jvs_parser::JVSPacket;
 use anyhow::{bail, Result};
-use num_enum::TryFromPrimitive;
+use num_enum::{FromPrimitive, IntoPrimitive, TryFromPrimitive};
 
 macro_rules! verbatim_parse {
     ($e:ident, $b:ident) => {
@@ -16,7 +16,21 @@ fn without_command(buf: &[u8]) -> Vec<u8> {
     Vec::from(&buf[1..])
 }
 
-#[derive(Debug, TryFromPrimitive, PartialEq)]
+// Body of a fixed-size command, rejecting anything short or over-long.
+fn fixed_body<const N: usize>(command_type: &LEDCommandType, buf: &[u8]) -> Result<[u8; N]> {
+    let body = buf.get(1..).unwrap_or_default();
+    match body.try_into() {
+        Ok(body) => Ok(body),
+        Err(_) => bail!(
+            "{:?} needs {} body bytes, got {}",
+            command_type,
+            N,
+            body.len()
+        ),
+    }
+}
+
+#[derive(Debug, Clone, Copy, TryFromPrimitive, PartialEq)]
 #[repr(u8)]
 pub enum LEDCommandType {
     Reset = 16,
@@ -36,7 +50,67 @@ pub enum LEDCommandType {
     SetTimeout = 17,
 }
 
-#[derive(Debug, PartialEq)]
+/// Brightness of the three FET-driven light strips on the cabinet.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FetLevels {
+    pub chassis: u8, // FET0
+    pub ring: u8,    // FET1
+    pub side: u8,    // FET2
+}
+
+/// LED indices covered by a `start`/`end`/`skip` run, as used by the multi-LED commands: from
+/// `start` up to (not including) `end`, stepping over `skip` LEDs between each one.
+pub fn led_range(start: u8, end: u8, skip: u8) -> impl Iterator<Item = u8> {
+    (start..end).step_by(skip as usize + 1)
+}
+
+/// Dot correction (per-channel current scaling) for a run of LEDs. Shares its layout with
+/// `SetMultiLED`, with the colour replaced by correction values.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DotCorrection {
+    pub start: u8,
+    pub end: u8,
+    pub skip: u8,
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub speed: u8,
+}
+impl DotCorrection {
+    fn parse(command_type: &LEDCommandType, buf: &[u8]) -> Result<Self> {
+        let [start, end, skip, r, g, b, speed] = fixed_body(command_type, buf)?;
+        Ok(DotCorrection {
+            start,
+            end,
+            skip,
+            r,
+            g,
+            b,
+            speed,
+        })
+    }
+
+    fn serialize(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&[
+            self.start, self.end, self.skip, self.r, self.g, self.b, self.speed,
+        ]);
+    }
+
+    pub fn channels(&self) -> [u8; 3] {
+        [self.r, self.g, self.b]
+    }
+
+    pub fn leds(&self) -> impl Iterator<Item = u8> {
+        led_range(self.start, self.end, self.skip)
+    }
+
+    /// Correction for LED `index`, if this command covers it.
+    pub fn for_led(&self, index: u8) -> Option<[u8; 3]> {
+        self.leds().any(|led| led == index).then(|| self.channels())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum LEDCommand {
     Reset,
     SetLED {
@@ -63,67 +137,120 @@ pub enum LEDCommand {
         b: u8,
         speed: u8,
     },
-    SetDc(Vec<u8>),
-    UpdateDc(Vec<u8>),
-    SetFet(Vec<u8>),
+    SetDc(DotCorrection),
+    UpdateDc(DotCorrection),
+    SetFet(FetLevels),
     Commit,
     GetBoardInfoCommand(Vec<u8>),
     GetProtocolVersionCommand(Vec<u8>),
     GetBoardStatusCommand(Vec<u8>),
-    EepromWrite(Vec<u8>),
-    EepromRead(Vec<u8>),
-    SetTimeout(Vec<u8>),
+    EepromWrite {
+        address: u8,
+        data: u8,
+    },
+    EepromRead {
+        address: u8,
+    },
+    /// How long the board waits without commands before giving up on the host. 0 disables it.
+    SetTimeout {
+        seconds: u16,
+    },
+    /// An opcode we don't know, kept verbatim so it still reaches the board.
+    Unknown {
+        opcode: u8,
+        body: Vec<u8>,
+    },
 }
 impl LEDCommand {
     pub fn parse(packet: &JVSPacket) -> Result<Self> {
         if packet.payload.is_empty() {
             bail!("Message payload was empty");
         }
-        let command_type = LEDCommandType::try_from(packet.payload[0])?;
+        let Ok(command_type) = LEDCommandType::try_from(packet.payload[0]) else {
+            return Ok(LEDCommand::Unknown {
+                opcode: packet.payload[0],
+                body: without_command(&packet.payload),
+            });
+        };
         match command_type {
-            LEDCommandType::Reset => Ok(LEDCommand::Reset),
-            LEDCommandType::SetLED => Ok(LEDCommand::SetLED {
-                index: packet.payload[1],
-                r: packet.payload[2],
-                g: packet.payload[3],
-                b: packet.payload[4],
-            }),
-            LEDCommandType::SetMultiLED => Ok(LEDCommand::SetMultiLED {
-                start: packet.payload[1],
-                end: packet.payload[2],
-                skip: packet.payload[3],
-                r: packet.payload[4],
-                g: packet.payload[5],
-                b: packet.payload[6],
-                speed: packet.payload[7],
-            }),
-            LEDCommandType::SetMultiLEDFade => Ok(LEDCommand::SetMultiLEDFade {
-                start: packet.payload[1],
-                end: packet.payload[2],
-                skip: packet.payload[3],
-                r: packet.payload[4],
-                g: packet.payload[5],
-                b: packet.payload[6],
-                speed: packet.payload[7],
-            }),
+            LEDCommandType::Reset => {
+                let [] = fixed_body(&command_type, &packet.payload)?;
+                Ok(LEDCommand::Reset)
+            }
+            LEDCommandType::SetLED => {
+                let [index, r, g, b] = fixed_body(&command_type, &packet.payload)?;
+                Ok(LEDCommand::SetLED { index, r, g, b })
+            }
+            LEDCommandType::SetMultiLED => {
+                let [start, end, skip, r, g, b, speed] =
+                    fixed_body(&command_type, &packet.payload)?;
+                Ok(LEDCommand::SetMultiLED {
+                    start,
+                    end,
+                    skip,
+                    r,
+                    g,
+                    b,
+                    speed,
+                })
+            }
+            LEDCommandType::SetMultiLEDFade => {
+                let [start, end, skip, r, g, b, speed] =
+                    fixed_body(&command_type, &packet.payload)?;
+                Ok(LEDCommand::SetMultiLEDFade {
+                    start,
+                    end,
+                    skip,
+                    r,
+                    g,
+                    b,
+                    speed,
+                })
+            }
             // Verbatim passthrough
             LEDCommandType::GetBoardInfoCommand => verbatim_parse!(GetBoardInfoCommand, packet),
-            LEDCommandType::SetDc => verbatim_parse!(SetDc, packet),
-            LEDCommandType::UpdateDc => verbatim_parse!(UpdateDc, packet),
-            LEDCommandType::SetFet => verbatim_parse!(SetFet, packet),
-            LEDCommandType::Commit => Ok(LEDCommand::Commit),
+            LEDCommandType::SetDc => {
+                DotCorrection::parse(&command_type, &packet.payload).map(LEDCommand::SetDc)
+            }
+            LEDCommandType::UpdateDc => {
+                DotCorrection::parse(&command_type, &packet.payload).map(LEDCommand::UpdateDc)
+            }
+            LEDCommandType::SetFet => {
+                let [chassis, ring, side] = fixed_body(&command_type, &packet.payload)?;
+                Ok(LEDCommand::SetFet(FetLevels {
+                    chassis,
+                    ring,
+                    side,
+                }))
+            }
+            LEDCommandType::Commit => {
+                let [] = fixed_body(&command_type, &packet.payload)?;
+                Ok(LEDCommand::Commit)
+            }
             LEDCommandType::GetProtocolVersionCommand => {
                 verbatim_parse!(GetProtocolVersionCommand, packet)
             }
             LEDCommandType::GetBoardStatusCommand => verbatim_parse!(GetBoardStatusCommand, packet),
-            LEDCommandType::EepromWrite => verbatim_parse!(EepromWrite, packet),
-            LEDCommandType::EepromRead => verbatim_parse!(EepromRead, packet),
-            LEDCommandType::SetTimeout => verbatim_parse!(SetTimeout, packet),
+            LEDCommandType::EepromWrite => {
+                let [address, data] = fixed_body(&command_type, &packet.payload)?;
+                Ok(LEDCommand::EepromWrite { address, data })
+            }
+            LEDCommandType::EepromRead => {
+                let [address] = fixed_body(&command_type, &packet.payload)?;
+                Ok(LEDCommand::EepromRead { address })
+            }
+            LEDCommandType::SetTimeout => {
+                let seconds = fixed_body(&command_type, &packet.payload)?;
+                Ok(LEDCommand::SetTimeout {
+                    seconds: u16::from_be_bytes(seconds),
+                })
+            }
         }
     }
 
-    pub fn get_type(&self) -> LEDCommandType {
-        match self {
+    /// `None` for `Unknown` commands; see `opcode` for those.
+    pub fn get_type(&self) -> Option<LEDCommandType> {
+        Some(match self {
             LEDCommand::Reset => LEDCommandType::Reset,
             LEDCommand::SetLED { .. } => LEDCommandType::SetLED,
             LEDCommand::SetMultiLED { .. } => LEDCommandType::SetMultiLED,
@@ -135,10 +262,18 @@ impl LEDCommand {
             LEDCommand::GetBoardInfoCommand(_) => LEDCommandType::GetBoardInfoCommand,
             LEDCommand::GetProtocolVersionCommand(_) => LEDCommandType::GetProtocolVersionCommand,
             LEDCommand::GetBoardStatusCommand(_) => LEDCommandType::GetBoardStatusCommand,
-            LEDCommand::EepromWrite(_) => LEDCommandType::EepromWrite,
-            LEDCommand::EepromRead(_) => LEDCommandType::EepromRead,
-            LEDCommand::SetTimeout(_) => LEDCommandType::SetTimeout,
+            LEDCommand::EepromWrite { .. } => LEDCommandType::EepromWrite,
+            LEDCommand::EepromRead { .. } => LEDCommandType::EepromRead,
+            LEDCommand::SetTimeout { .. } => LEDCommandType::SetTimeout,
+            LEDCommand::Unknown { .. } => return None,
+        })
+    }
+
+    pub fn opcode(&self) -> u8 {
+        if let LEDCommand::Unknown { opcode, .. } = self {
+            return *opcode;
         }
+        self.get_type().expect("known commands have a type") as u8
     }
 
     fn serialize_cmd_body(&self, buf: &mut Vec<u8>) {
@@ -162,34 +297,43 @@ impl LEDCommand {
                 b,
                 speed,
             } => buf.extend_from_slice(&[*start, *end, *skip, *r, *g, *b, *speed]),
+            LEDCommand::SetFet(FetLevels {
+                chassis,
+                ring,
+                side,
+            }) => buf.extend_from_slice(&[*chassis, *ring, *side]),
+            LEDCommand::EepromWrite { address, data } => buf.extend_from_slice(&[*address, *data]),
+            LEDCommand::EepromRead { address } => buf.push(*address),
+            LEDCommand::SetTimeout { seconds } => buf.extend_from_slice(&seconds.to_be_bytes()),
             LEDCommand::Reset => (),
             LEDCommand::Commit => (),
-            LEDCommand::SetDc(data)
-            | LEDCommand::UpdateDc(data)
-            | LEDCommand::SetFet(data)
-            | LEDCommand::GetBoardInfoCommand(data)
+            LEDCommand::SetDc(dc) | LEDCommand::UpdateDc(dc) => dc.serialize(buf),
+            LEDCommand::GetBoardInfoCommand(data)
             | LEDCommand::GetProtocolVersionCommand(data)
             | LEDCommand::GetBoardStatusCommand(data)
-            | LEDCommand::EepromWrite(data)
-            | LEDCommand::EepromRead(data)
-            | LEDCommand::SetTimeout(data) => buf.extend_from_slice(data),
+            | LEDCommand::Unknown { body: data, .. } => buf.extend_from_slice(data),
         };
     }
 
+    /// An `Ok` reply that echoes this command's body, which boards don't do: acks carry no body
+    /// and queries answer with their own.
+    #[deprecated(note = "echoes the request body; build an `LEDReply` instead")]
     pub fn serialize_reply(&self, buf: &mut Vec<u8>) {
-        buf.push(1); // Status
-        buf.push(self.get_type() as u8);
-        buf.push(1); // Report
+        buf.push(ReplyStatus::Ok.into());
+        buf.push(self.opcode());
+        buf.push(ReplyReport::Ok.into());
         self.serialize_cmd_body(buf);
     }
 
+    #[deprecated(note = "echoes the request body; build an `LEDReply` instead")]
+    #[allow(deprecated)]
     pub fn serialize_reply_to_jvs(&self, jvs_packet: &mut JVSPacket) {
         jvs_packet.payload.clear();
         self.serialize_reply(&mut jvs_packet.payload)
     }
 
     pub fn serialize(&self, buf: &mut Vec<u8>) {
-        buf.push(self.get_type() as u8);
+        buf.push(self.opcode());
         self.serialize_cmd_body(buf);
     }
 
@@ -198,3 +342,260 @@ impl LEDCommand {
         self.serialize(&mut jvs_packet.payload)
     }
 }
+
+/// Transport-level status of a reply; anything but `Ok` means the board didn't get the request
+/// intact.
+#[derive(Debug, Clone, Copy, PartialEq, FromPrimitive, IntoPrimitive)]
+#[repr(u8)]
+pub enum ReplyStatus {
+    Ok = 1,
+    SumError = 2,
+    ParityError = 3,
+    FramingError = 4,
+    OverrunError = 5,
+    BufferOverflow = 6,
+    #[num_enum(catch_all)]
+    Unknown(u8),
+}
+
+/// Command-level result of a reply.
+#[derive(Debug, Clone, Copy, PartialEq, FromPrimitive, IntoPrimitive)]
+#[repr(u8)]
+pub enum ReplyReport {
+    Ok = 1,
+    Busy = 2,
+    UnknownCommand = 3,
+    ParameterError = 4,
+    #[num_enum(catch_all)]
+    Unknown(u8),
+}
+
+/// Identity the board reports for `GetBoardInfoCommand`, e.g. `15070-04`, `0xFF`, firmware `1`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoardInfo {
+    pub part_number: String,
+    pub firmware_version: u8,
+    /// Anything the board sends after the firmware version.
+    pub extra: Vec<u8>,
+}
+impl BoardInfo {
+    const PART_NUMBER_END: u8 = 0xFF;
+
+    pub fn new(part_number: &str, firmware_version: u8) -> Self {
+        BoardInfo {
+            part_number: part_number.to_owned(),
+            firmware_version,
+            extra: Vec::new(),
+        }
+    }
+
+    pub fn parse(buf: &[u8]) -> Result<Self> {
+        let Some(end) = buf.iter().position(|byte| *byte == Self::PART_NUMBER_END) else {
+            bail!("Board info has no part number terminator");
+        };
+        let Some(firmware_version) = buf.get(end + 1) else {
+            bail!("Board info has no firmware version");
+        };
+        Ok(BoardInfo {
+            part_number: String::from_utf8(buf[..end].to_vec())?,
+            firmware_version: *firmware_version,
+            extra: buf[end + 2..].to_vec(),
+        })
+    }
+
+    pub fn serialize(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self.part_number.as_bytes());
+        buf.push(Self::PART_NUMBER_END);
+        buf.push(self.firmware_version);
+        buf.extend_from_slice(&self.extra);
+    }
+}
+impl std::fmt::Display for BoardInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} (firmware {})",
+            self.part_number, self.firmware_version
+        )
+    }
+}
+
+/// Reply to `GetProtocolVersionCommand`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProtocolVersion {
+    /// 1 when running the application firmware, rather than the bootloader.
+    pub mode: u8,
+    pub major: u8,
+    pub minor: u8,
+}
+impl ProtocolVersion {
+    pub const APPLICATION_MODE: u8 = 1;
+
+    pub fn parse(buf: &[u8]) -> Result<Self> {
+        let [mode, major, minor] = buf[..] else {
+            bail!("Protocol version needs 3 bytes, got {}", buf.len());
+        };
+        Ok(ProtocolVersion { mode, major, minor })
+    }
+
+    pub fn serialize(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&[self.mode, self.major, self.minor]);
+    }
+}
+impl std::str::FromStr for ProtocolVersion {
+    type Err = anyhow::Error;
+
+    /// Parses `MAJOR.MINOR`, e.g. `1.4`, as an application firmware version.
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((major, minor)) = s.split_once('.') else {
+            bail!("Expected `<major>.<minor>`, got `{}`", s);
+        };
+        Ok(ProtocolVersion {
+            mode: Self::APPLICATION_MODE,
+            major: major.parse()?,
+            minor: minor.parse()?,
+        })
+    }
+}
+impl std::fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{} (mode {})", self.major, self.minor, self.mode)
+    }
+}
+
+/// Reply to `GetBoardStatusCommand`. All zeroes is a healthy board.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BoardStatus {
+    pub timeout_status: u8,
+    pub timeout_sec: u8,
+    pub pwm_io: u8,
+    pub fet_timeout: u8,
+}
+impl BoardStatus {
+    pub fn parse(buf: &[u8]) -> Result<Self> {
+        let [timeout_status, timeout_sec, pwm_io, fet_timeout] = buf[..] else {
+            bail!("Board status needs 4 bytes, got {}", buf.len());
+        };
+        Ok(BoardStatus {
+            timeout_status,
+            timeout_sec,
+            pwm_io,
+            fet_timeout,
+        })
+    }
+
+    pub fn serialize(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&[
+            self.timeout_status,
+            self.timeout_sec,
+            self.pwm_io,
+            self.fet_timeout,
+        ]);
+    }
+}
+impl std::str::FromStr for BoardStatus {
+    type Err = anyhow::Error;
+
+    /// Parses the four status bytes as a comma-separated list, e.g. `0,0,0,0`.
+    fn from_str(s: &str) -> Result<Self> {
+        let bytes = s
+            .split(',')
+            .map(|byte| byte.trim().parse())
+            .collect::<Result<Vec<u8>, _>>()?;
+        Self::parse(&bytes)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LEDReplyBody {
+    /// Plain acknowledgement with nothing after the report byte.
+    Ack,
+    BoardInfo(BoardInfo),
+    ProtocolVersion(ProtocolVersion),
+    BoardStatus(BoardStatus),
+    /// The byte stored at the requested address.
+    EepromRead(u8),
+    /// Trailing bytes on a reply to a command that doesn't usually carry any.
+    Other(Vec<u8>),
+}
+
+/// A response from the LED board, shaped `[status, command, report, body...]`.
+#[derive(Debug, PartialEq)]
+pub struct LEDReply {
+    pub status: ReplyStatus,
+    /// Opcode of the command being answered, which may be one we don't know.
+    pub opcode: u8,
+    pub report: ReplyReport,
+    pub body: LEDReplyBody,
+}
+impl LEDReply {
+    /// A successful reply to `command`.
+    pub fn new(command: LEDCommandType, body: LEDReplyBody) -> Self {
+        LEDReply {
+            status: ReplyStatus::Ok,
+            opcode: command as u8,
+            report: ReplyReport::Ok,
+            body,
+        }
+    }
+
+    pub fn parse(packet: &JVSPacket) -> Result<Self> {
+        let [status, opcode, report, ..] = packet.payload[..] else {
+            bail!("Reply needs at least 3 bytes, got {}", packet.payload.len());
+        };
+        let body = Vec::from(&packet.payload[3..]);
+        let body = match LEDCommandType::try_from(opcode).ok() {
+            _ if body.is_empty() => LEDReplyBody::Ack,
+            Some(LEDCommandType::GetBoardInfoCommand) => {
+                LEDReplyBody::BoardInfo(BoardInfo::parse(&body)?)
+            }
+            Some(LEDCommandType::GetProtocolVersionCommand) => {
+                LEDReplyBody::ProtocolVersion(ProtocolVersion::parse(&body)?)
+            }
+            Some(LEDCommandType::GetBoardStatusCommand) => {
+                LEDReplyBody::BoardStatus(BoardStatus::parse(&body)?)
+            }
+            Some(LEDCommandType::EepromRead) => {
+                let [data] = body[..] else {
+                    bail!("EEPROM read reply needs 1 byte, got {}", body.len());
+                };
+                LEDReplyBody::EepromRead(data)
+            }
+            _ => LEDReplyBody::Other(body),
+        };
+        Ok(LEDReply {
+            status: ReplyStatus::from(status),
+            opcode,
+            report: ReplyReport::from(report),
+            body,
+        })
+    }
+
+    /// Type of the command being answered, `None` for opcodes we don't know.
+    pub fn command(&self) -> Option<LEDCommandType> {
+        LEDCommandType::try_from(self.opcode).ok()
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.status == ReplyStatus::Ok && self.report == ReplyReport::Ok
+    }
+
+    pub fn serialize(&self, buf: &mut Vec<u8>) {
+        buf.push(self.status.into());
+        buf.push(self.opcode);
+        buf.push(self.report.into());
+        match &self.body {
+            LEDReplyBody::Ack => (),
+            LEDReplyBody::BoardInfo(info) => info.serialize(buf),
+            LEDReplyBody::ProtocolVersion(version) => version.serialize(buf),
+            LEDReplyBody::BoardStatus(status) => status.serialize(buf),
+            LEDReplyBody::EepromRead(data) => buf.push(*data),
+            LEDReplyBody::Other(data) => buf.extend_from_slice(data),
+        }
+    }
+
+    pub fn serialize_to_jvs(&self, jvs_packet: &mut JVSPacket) {
+        jvs_packet.payload.clear();
+        self.serialize(&mut jvs_packet.payload)
+    }
+}
@@ -0,0 +1,254 @@
+use anyhow::{bail, Result};
+use serialport::{DataBits, FlowControl, Parity, SerialPort, StopBits};
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Line settings for one port. Parsed from comma-separated `key=value` pairs, e.g.
+/// `baud=38400,parity=even,dtr=on`; anything left out keeps the 15070 board's defaults.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SerialSettings {
+    pub baud_rate: u32,
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub flow_control: FlowControl,
+    pub timeout: Duration,
+    /// DTR and RTS levels to set once open. `None` leaves them as the driver opened them.
+    pub dtr: Option<bool>,
+    pub rts: Option<bool>,
+}
+impl Default for SerialSettings {
+    fn default() -> Self {
+        SerialSettings {
+            baud_rate: 115200,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            flow_control: FlowControl::None,
+            timeout: Duration::from_secs(1),
+            dtr: None,
+            rts: None,
+        }
+    }
+}
+impl SerialSettings {
+    /// Applies one `key=value` setting, as accepted by `from_str`.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "baud" => self.baud_rate = value.parse()?,
+            "data" => {
+                self.data_bits = match value {
+                    "5" => DataBits::Five,
+                    "6" => DataBits::Six,
+                    "7" => DataBits::Seven,
+                    "8" => DataBits::Eight,
+                    _ => bail!("Data bits must be 5 to 8, got `{}`", value),
+                }
+            }
+            "parity" => {
+                self.parity = match value {
+                    "none" => Parity::None,
+                    "odd" => Parity::Odd,
+                    "even" => Parity::Even,
+                    _ => bail!("Parity must be none, odd or even, got `{}`", value),
+                }
+            }
+            "stop" => {
+                self.stop_bits = match value {
+                    "1" => StopBits::One,
+                    "2" => StopBits::Two,
+                    _ => bail!("Stop bits must be 1 or 2, got `{}`", value),
+                }
+            }
+            "flow" => {
+                self.flow_control = match value {
+                    "none" => FlowControl::None,
+                    "software" => FlowControl::Software,
+                    "hardware" => FlowControl::Hardware,
+                    _ => bail!(
+                        "Flow control must be none, software or hardware, got `{}`",
+                        value
+                    ),
+                }
+            }
+            "timeout" => {
+                self.timeout = Duration::from_millis(value.parse()?);
+                // Reads would return straight away, and the reading thread would spin.
+                if self.timeout.is_zero() {
+                    bail!("Timeout must be at least 1ms");
+                }
+            }
+            "dtr" => self.dtr = Some(parse_level(value)?),
+            "rts" => self.rts = Some(parse_level(value)?),
+            _ => bail!(
+                "Unknown serial setting `{}`, expected baud, data, parity, stop, flow, timeout, dtr or rts",
+                key
+            ),
+        }
+        Ok(())
+    }
+}
+impl FromStr for SerialSettings {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut settings = SerialSettings::default();
+        for_each_setting(s, |key, value| settings.set(key, value))?;
+        Ok(settings)
+    }
+}
+
+/// Calls `set` with each `key=value` of a comma-separated list, as taken by `--alls-serial`,
+/// `--tee` and `--pair`. A bare `key` comes through with an empty value.
+pub fn for_each_setting(s: &str, mut set: impl FnMut(&str, &str) -> Result<()>) -> Result<()> {
+    for pair in s.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        set(key, value)?;
+    }
+    Ok(())
+}
+
+pub fn parse_level(value: &str) -> Result<bool> {
+    Ok(match value {
+        "on" => true,
+        "off" => false,
+        _ => bail!("Expected on or off, got `{}`", value),
+    })
+}
+
+fn open(path: &Path, settings: &SerialSettings) -> serialport::Result<Box<dyn SerialPort>> {
+    let mut port = serialport::new(path.to_string_lossy(), settings.baud_rate)
+        .data_bits(settings.data_bits)
+        .parity(settings.parity)
+        .stop_bits(settings.stop_bits)
+        .flow_control(settings.flow_control)
+        .timeout(settings.timeout)
+        .open()?;
+    if let Some(dtr) = settings.dtr {
+        port.write_data_terminal_ready(dtr)?;
+    }
+    if let Some(rts) = settings.rts {
+        port.write_request_to_send(rts)?;
+    }
+    Ok(port)
+}
+
+/// A serial port that survives its USB adapter being unplugged and plugged back in.
+///
+/// One thread reads; when the device goes away that read waits, reopening the path until it
+/// comes back. Writes meanwhile are dropped, so whatever is on the other end keeps running.
+pub struct ReconnectingPort {
+    name: &'static str,
+    path: PathBuf,
+    settings: SerialSettings,
+    reader: Mutex<Option<Box<dyn SerialPort>>>,
+    writer: Mutex<Option<Box<dyn SerialPort>>>,
+    reconnects: AtomicU32,
+}
+impl ReconnectingPort {
+    /// Opens `path`, failing straight away if it isn't there. `name` is used in logs.
+    pub fn open(name: &'static str, path: PathBuf, settings: SerialSettings) -> Result<Self> {
+        let reader = open(&path, &settings)?;
+        let writer = reader.try_clone()?;
+        Ok(ReconnectingPort {
+            name,
+            path,
+            settings,
+            reader: Mutex::new(Some(reader)),
+            writer: Mutex::new(Some(writer)),
+            reconnects: AtomicU32::new(0),
+        })
+    }
+
+    /// Like `open`, but if `path` isn't there yet, starts disconnected and waits for it as if it
+    /// had been unplugged. Its arrival counts as a reconnect.
+    pub fn open_or_wait(name: &'static str, path: PathBuf, settings: SerialSettings) -> Self {
+        match Self::open(name, path.clone(), settings.clone()) {
+            Ok(port) => port,
+            Err(err) => {
+                tracing::warn!(
+                    "{} port {:?} isn't there, waiting for it: {:?}",
+                    name,
+                    path,
+                    err
+                );
+                ReconnectingPort {
+                    name,
+                    path,
+                    settings,
+                    reader: Mutex::new(None),
+                    writer: Mutex::new(None),
+                    reconnects: AtomicU32::new(0),
+                }
+            }
+        }
+    }
+
+    /// Reads whatever the port has buffered, waiting through read timeouts and disconnects
+    /// until something arrives. `None` once `stop` is raised.
+    pub fn read(&self, buf: &mut [u8], stop: &AtomicBool) -> Option<usize> {
+        let mut reader = self.reader.lock().unwrap();
+        while !stop.load(Ordering::SeqCst) {
+            let Some(port) = reader.as_mut() else {
+                std::thread::sleep(RECONNECT_INTERVAL);
+                *reader = self.reopen();
+                continue;
+            };
+            match port.read(buf) {
+                Ok(len) if len > 0 => return Some(len),
+                // A signal landing on this thread interrupts the wait; the port is still fine.
+                Err(err) if matches!(err.kind(), ErrorKind::TimedOut | ErrorKind::Interrupted) => {}
+                // A hung-up tty reads as end of file rather than timing out.
+                result => {
+                    tracing::warn!(
+                        "{} port {:?} disconnected: {:?}",
+                        self.name,
+                        self.path,
+                        result
+                    );
+                    // The device only comes back under the same name once nothing holds it open.
+                    *self.writer.lock().unwrap() = None;
+                    *reader = None;
+                }
+            }
+        }
+        None
+    }
+
+    fn reopen(&self) -> Option<Box<dyn SerialPort>> {
+        let reader = open(&self.path, &self.settings).ok()?;
+        let writer = reader.try_clone().ok()?;
+        tracing::info!("{} port {:?} reconnected", self.name, self.path);
+        *self.writer.lock().unwrap() = Some(writer);
+        self.reconnects.fetch_add(1, Ordering::SeqCst);
+        Some(reader)
+    }
+
+    /// How many times the device has come back after a disconnect.
+    pub fn reconnects(&self) -> u32 {
+        self.reconnects.load(Ordering::SeqCst)
+    }
+
+    /// Writes `data` if the port is connected, and drops it otherwise.
+    pub fn write_all(&self, data: &[u8]) {
+        let mut writer = self.writer.lock().unwrap();
+        let Some(port) = writer.as_mut() else {
+            return;
+        };
+        // A disconnect is picked up, and reconnected, by the reading thread.
+        if let Err(err) = port.write_all(data) {
+            tracing::warn!(
+                "Couldn't write to {} port {:?}: {:?}",
+                self.name,
+                self.path,
+                err
+            );
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use anyhow::{anyhow, bail, Result};
+use std::panic::AssertUnwindSafe;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/// What to do when one of the proxy's threads fails.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FailurePolicy {
+    /// Start the failed thread again, leaving the others running.
+    Restart,
+    /// Stop everything and exit with an error.
+    Shutdown,
+}
+impl FromStr for FailurePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "restart" => FailurePolicy::Restart,
+            "shutdown" => FailurePolicy::Shutdown,
+            _ => bail!(
+                "Unknown failure policy `{}`, expected restart or shutdown",
+                s
+            ),
+        })
+    }
+}
+
+/// Raises its flag when dropped, so a thread can signal its exit however it ends.
+pub struct SetOnDrop<'a>(pub &'a AtomicBool);
+impl Drop for SetOnDrop<'_> {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Runs `body` until it returns `Ok` (meaning `stop` was raised), handling errors and panics
+/// according to `policy`. Raises `stop` on the way out, so the other threads follow.
+pub fn supervise(
+    name: &str,
+    policy: FailurePolicy,
+    stop: &AtomicBool,
+    mut body: impl FnMut() -> Result<()>,
+) -> Result<()> {
+    let _exit = SetOnDrop(stop);
+    loop {
+        let err = match std::panic::catch_unwind(AssertUnwindSafe(&mut body)) {
+            Ok(Ok(())) => return Ok(()),
+            Ok(Err(err)) => err,
+            Err(panic) => anyhow!("panicked: {}", panic_message(&*panic)),
+        };
+        let err = err.context(format!("{} failed", name));
+        if policy == FailurePolicy::Shutdown || stop.load(Ordering::SeqCst) {
+            tracing::error!("{:?}, shutting down", err);
+            return Err(err);
+        }
+        tracing::error!("{:?}, restarting in {:?}", err, RESTART_DELAY);
+        std::thread::sleep(RESTART_DELAY);
+    }
+}
+
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> &str {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
@@ -0,0 +1,171 @@
+use crate::proxy::{frame_commands, READ_BUFFER_SIZE};
+use crate::serial::{for_each_setting, ReconnectingPort, SerialSettings};
+use anyhow::{bail, Result};
+use mailight_rs::jvs_parser::JVSPacket;
+use mailight_rs::sega_led::{FetLevels, LEDCommand};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::AtomicBool;
+
+const RGB: [usize; 3] = [0, 1, 2];
+
+/// An extra board that mirrors the primary one. Parsed from comma-separated `key=value` pairs,
+/// e.g. `port=/dev/ttyUSB4,order=grb,brightness=50`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TeeTarget {
+    pub port: PathBuf,
+    /// Which of the incoming red, green and blue ends up in each of this board's channels.
+    pub channel_order: [usize; 3],
+    /// Percentage applied to every colour and FET level.
+    pub brightness: u8,
+}
+impl TeeTarget {
+    /// Applies one `key=value` setting, as accepted by `from_str`.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "port" => self.port = value.into(),
+            "order" => self.channel_order = parse_channel_order(value)?,
+            "brightness" => {
+                self.brightness = value.parse()?;
+                if self.brightness > 100 {
+                    bail!("Brightness is a percentage, got {}", self.brightness);
+                }
+            }
+            _ => bail!(
+                "Unknown tee setting `{}`, expected port, order or brightness",
+                key
+            ),
+        }
+        Ok(())
+    }
+}
+impl FromStr for TeeTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut target = TeeTarget {
+            port: PathBuf::new(),
+            channel_order: RGB,
+            brightness: 100,
+        };
+        for_each_setting(s, |key, value| target.set(key, value))?;
+        if target.port.as_os_str().is_empty() {
+            bail!("A tee needs `port=<port>`");
+        }
+        Ok(target)
+    }
+}
+
+// `rbg` puts blue in the green channel and green in the blue one, like `--fix-rbg`.
+fn parse_channel_order(s: &str) -> Result<[usize; 3]> {
+    let mut order = [0; 3];
+    let mut seen = [false; 3];
+    if s.len() != 3 {
+        bail!("Channel order needs each of r, g and b once, got `{}`", s);
+    }
+    for (slot, channel) in order.iter_mut().zip(s.chars()) {
+        *slot = match channel {
+            'r' => 0,
+            'g' => 1,
+            'b' => 2,
+            _ => bail!("Channel order needs each of r, g and b once, got `{}`", s),
+        };
+        if std::mem::replace(&mut seen[*slot], true) {
+            bail!("Channel order needs each of r, g and b once, got `{}`", s);
+        }
+    }
+    Ok(order)
+}
+
+impl TeeTarget {
+    fn scale(&self, level: u8) -> u8 {
+        (level as u16 * self.brightness as u16 / 100) as u8
+    }
+
+    fn color(&self, r: &mut u8, g: &mut u8, b: &mut u8) {
+        let incoming = [*r, *g, *b];
+        let [r_from, g_from, b_from] = self.channel_order;
+        *r = self.scale(incoming[r_from]);
+        *g = self.scale(incoming[g_from]);
+        *b = self.scale(incoming[b_from]);
+    }
+
+    /// `command` as this board should get it.
+    pub fn transform(&self, command: &LEDCommand) -> LEDCommand {
+        let mut command = command.clone();
+        match &mut command {
+            LEDCommand::SetLED { r, g, b, .. }
+            | LEDCommand::SetMultiLED { r, g, b, .. }
+            | LEDCommand::SetMultiLEDFade { r, g, b, .. } => self.color(r, g, b),
+            LEDCommand::SetFet(FetLevels {
+                chassis,
+                ring,
+                side,
+            }) => {
+                *chassis = self.scale(*chassis);
+                *ring = self.scale(*ring);
+                *side = self.scale(*side);
+            }
+            _ => (),
+        }
+        command
+    }
+}
+
+/// A connected `TeeTarget`.
+pub struct Tee {
+    pub target: TeeTarget,
+    pub port: ReconnectingPort,
+}
+impl Tee {
+    /// Never fails: a mirror board that isn't plugged in yet mustn't stop the primary one.
+    pub fn open(target: TeeTarget, settings: SerialSettings) -> Self {
+        let port = ReconnectingPort::open_or_wait("LED tee", target.port.clone(), settings);
+        Tee { target, port }
+    }
+
+    /// Sends `(source_id, dest_id, command)`s, transformed for this board.
+    pub fn send_commands(&self, commands: &[(u8, u8, LEDCommand)]) -> Result<()> {
+        let transformed: Vec<_> = commands
+            .iter()
+            .map(|(source_id, dest_id, cmd)| (*source_id, *dest_id, self.target.transform(cmd)))
+            .collect();
+        let mut send_buffer = Vec::new();
+        frame_commands(&transformed, &mut send_buffer)?;
+        self.write(&send_buffer);
+        Ok(())
+    }
+
+    /// Sends packets as the primary board got them. Those that parse are transformed for this
+    /// board; the rest go through untouched, as they did to the primary.
+    pub fn send(&self, packets: &[JVSPacket]) -> Result<()> {
+        let mut send_buffer = Vec::new();
+        for packet in packets {
+            match LEDCommand::parse(packet) {
+                Ok(cmd) => {
+                    let mut transformed = JVSPacket::new(packet.source_id, packet.dest_id);
+                    self.target
+                        .transform(&cmd)
+                        .serialize_to_jvs(&mut transformed);
+                    transformed.serialize(&mut send_buffer)?;
+                }
+                Err(_) => packet.serialize(&mut send_buffer)?,
+            }
+        }
+        self.write(&send_buffer);
+        Ok(())
+    }
+
+    fn write(&self, data: &[u8]) {
+        if !data.is_empty() {
+            self.port.write_all(data);
+        }
+    }
+
+    /// Reads and throws away the board's replies until `stop` is raised; only the primary
+    /// board answers the game.
+    pub fn drain(&self, stop: &AtomicBool) {
+        let mut buf = [0u8; READ_BUFFER_SIZE];
+        while self.port.read(&mut buf, stop).is_some() {}
+    }
+}
@@ -1,5 +1,13 @@
-use crate::jvs_parser::{JVSPacket, SegaJVSReader};
-use crate::sega_led;
+use crate::jvs_parser::{
+    JVSPacket, JvsBus, JvsBusError, JvsError, JvsErrorCounts, PayloadTooLong, SegaJVSReader,
+    BROADCAST_ADDRESS, HOST_ADDRESS, MAX_PAYLOAD_LEN,
+};
+use crate::led_board::{self, VirtualLedBoard, DEFAULT_DOT_CORRECTION};
+use crate::sega_led::{
+    self, BoardInfo, BoardStatus, FetLevels, LEDCommandType, LEDReply, LEDReplyBody,
+    ProtocolVersion, ReplyReport,
+};
+use std::time::Duration;
 
 #[test]
 fn test_serialization() {
@@ -7,14 +15,591 @@ fn test_serialization() {
     let mut jvs = SegaJVSReader::default();
     let mut new_buf = Vec::new();
     for byte in test_data {
-        if let Some(packet) = jvs.read_byte(*byte) {
-            let cmd = sega_led::LEDCommand::parse(packet).expect("led command");
+        if let Some(packet) = jvs.read_byte(*byte).expect("valid jvs data") {
+            let cmd = sega_led::LEDCommand::parse(&packet).expect("led command");
             // Create our own version of the same packet.
             let mut new_pkt = JVSPacket::new(packet.source_id, packet.dest_id);
             cmd.serialize_to_jvs(&mut new_pkt);
-            new_pkt.serialize(&mut new_buf);
+            new_pkt.serialize(&mut new_buf).unwrap();
         }
     }
     // Our version should look the same as the input.
     assert_eq!(test_data, new_buf.as_slice());
+    assert_eq!(*jvs.error_counts(), JvsErrorCounts::default());
+}
+
+#[test]
+fn test_reader_errors() {
+    let mut jvs = SegaJVSReader::default();
+    // Line noise before the first frame.
+    assert_eq!(jvs.read_byte(0x12), Err(JvsError::GarbageBeforeSync(0x12)));
+
+    // A frame cut short by a new sync byte.
+    for byte in [0xE0, 0x01, 0x02] {
+        assert_eq!(jvs.read_byte(byte), Ok(None));
+    }
+    assert_eq!(jvs.read_byte(0xE0), Err(JvsError::UnexpectedSync));
+
+    // A frame with a bad checksum: 0x01 + 0x02 + 0x01 + 0x10 = 0x14.
+    for byte in [0x01, 0x02, 0x01, 0x10] {
+        assert_eq!(jvs.read_byte(byte), Ok(None));
+    }
+    assert_eq!(
+        jvs.read_byte(0x15),
+        Err(JvsError::BadChecksum {
+            expected: 0x14,
+            actual: 0x15
+        })
+    );
+
+    // A good frame, followed by trailing junk.
+    for byte in [0xE0, 0x01, 0x02, 0x01, 0x10] {
+        assert_eq!(jvs.read_byte(byte), Ok(None));
+    }
+    let packet = jvs.read_byte(0x14).unwrap().expect("complete packet");
+    assert_eq!((packet.dest_id, packet.source_id), (0x01, 0x02));
+    assert_eq!(packet.payload, vec![0x10]);
+    assert_eq!(jvs.read_byte(0x33), Err(JvsError::DataWhileReady(0x33)));
+
+    let counts = jvs.error_counts();
+    assert_eq!(counts.garbage_before_sync, 1);
+    assert_eq!(counts.unexpected_sync, 1);
+    assert_eq!(counts.bad_checksum, 1);
+    assert_eq!(counts.data_while_ready, 1);
+}
+
+#[test]
+fn test_feed_across_chunks() {
+    let mut encoded = Vec::new();
+    for index in 0..3u8 {
+        let mut packet = JVSPacket::new(0x02, 0x01);
+        packet.payload = vec![0x31, index, 0xE0, 0x00, 0xFF];
+        packet.serialize(&mut encoded).unwrap();
+    }
+
+    // Split the stream at every possible point; the partial frame must carry over.
+    for split in 0..encoded.len() {
+        let mut jvs = SegaJVSReader::default();
+        let (head, tail) = encoded.split_at(split);
+        let mut packets: Vec<JVSPacket> = Vec::new();
+        for chunk in [head, tail] {
+            packets.extend(
+                jvs.feed(chunk)
+                    .map(|result| result.expect("valid jvs data")),
+            );
+        }
+        assert_eq!(packets.len(), 3);
+        for (index, packet) in packets.iter().enumerate() {
+            assert_eq!(packet.payload, vec![0x31, index as u8, 0xE0, 0x00, 0xFF]);
+        }
+    }
+}
+
+#[test]
+fn test_payload_lengths() {
+    for len in 0..=255usize {
+        for fill in [0x00, 0x01, 0xD0, 0xE0, 0xFF] {
+            let mut packet = JVSPacket::new(0x02, 0x01);
+            packet.payload = vec![fill; len];
+            let mut encoded = Vec::new();
+            packet.serialize(&mut encoded).unwrap();
+
+            let mut jvs = SegaJVSReader::default();
+            let decoded: Vec<JVSPacket> = jvs
+                .feed(&encoded)
+                .map(|result| result.expect("valid jvs data"))
+                .collect();
+            assert_eq!(decoded, vec![packet], "len {} fill {:#04x}", len, fill);
+        }
+    }
+}
+
+#[test]
+fn test_payload_too_long() {
+    assert!(JVSPacket::with_payload(0x02, 0x01, vec![0; MAX_PAYLOAD_LEN]).is_ok());
+    assert_eq!(
+        JVSPacket::with_payload(0x02, 0x01, vec![0; MAX_PAYLOAD_LEN + 1]),
+        Err(PayloadTooLong(MAX_PAYLOAD_LEN + 1))
+    );
+
+    // Payloads grown after construction are still refused at serialization time.
+    let mut packet = JVSPacket::new(0x02, 0x01);
+    packet.payload = vec![0; 300];
+    let mut encoded = Vec::new();
+    assert_eq!(packet.serialize(&mut encoded), Err(PayloadTooLong(300)));
+    assert!(encoded.is_empty());
+}
+
+#[test]
+fn test_empty_payload_completes() {
+    let mut jvs = SegaJVSReader::default();
+    for byte in [0xE0, 0x01, 0x02, 0x00] {
+        assert_eq!(jvs.read_byte(byte), Ok(None));
+    }
+    // The next byte is the checksum, not payload.
+    let packet = jvs.read_byte(0x03).unwrap().expect("complete packet");
+    assert!(packet.payload.is_empty());
+    assert_eq!(jvs.read_byte(0xE0), Ok(None));
+}
+
+#[test]
+fn test_escaping_round_trip() {
+    // Every value in every position of the frame, including ones that land the checksum on
+    // the sync or escape byte.
+    let mut encoded = Vec::new();
+    let mut packets = Vec::new();
+    for value in 0..=255u8 {
+        let mut packet = JVSPacket::new(value, value.wrapping_add(0x10));
+        packet.payload = vec![value, 0xD0, value, 0xE0];
+        packet.serialize(&mut encoded).unwrap();
+        packets.push(packet);
+
+        let mut packet = JVSPacket::new(0x01, 0x02);
+        packet.payload = vec![value];
+        packet.serialize(&mut encoded).unwrap();
+        packets.push(packet);
+    }
+
+    // The only unescaped sync bytes left are the frame starts.
+    assert_eq!(
+        encoded.iter().filter(|byte| **byte == 0xE0).count(),
+        packets.len()
+    );
+
+    let mut jvs = SegaJVSReader::default();
+    let decoded: Vec<JVSPacket> = jvs
+        .feed(&encoded)
+        .map(|result| result.expect("valid jvs data"))
+        .collect();
+    assert_eq!(decoded, packets);
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn test_codec_round_trip() {
+    use crate::jvs_codec::JvsCodec;
+    use bytes::BytesMut;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    let mut codec = JvsCodec::new();
+    let mut stream = BytesMut::new();
+    let mut packets = Vec::new();
+    for value in [0x00, 0xD0, 0xE0, 0xFF] {
+        let mut packet = JVSPacket::new(0x02, 0x01);
+        packet.payload = vec![0x31, value, value, value, value];
+        codec.encode(packet.clone(), &mut stream).unwrap();
+        packets.push(packet);
+    }
+
+    // Hand the decoder a byte at a time, the way a slow port would.
+    let mut buffered = BytesMut::new();
+    let mut decoded = Vec::new();
+    for byte in stream.iter() {
+        buffered.extend_from_slice(&[*byte]);
+        while let Some(packet) = codec.decode(&mut buffered).unwrap() {
+            decoded.push(packet);
+        }
+    }
+    assert_eq!(decoded, packets);
+    assert_eq!(*codec.error_counts(), JvsErrorCounts::default());
+}
+
+#[test]
+fn test_bus_routing() {
+    let mut bus = JvsBus::new();
+    let echo = |request: &JVSPacket| {
+        let mut reply = request.reply();
+        reply.payload = request.payload.clone();
+        Some(reply)
+    };
+    assert_eq!(bus.attach(echo), Ok(1));
+    bus.attach_at(5, |_: &JVSPacket| None).unwrap();
+    assert_eq!(bus.attach(echo), Ok(2));
+    assert_eq!(
+        bus.attach_at(5, |_: &JVSPacket| None),
+        Err(JvsBusError::AddressInUse(5))
+    );
+    assert_eq!(
+        bus.attach_at(BROADCAST_ADDRESS, echo),
+        Err(JvsBusError::ReservedAddress(BROADCAST_ADDRESS))
+    );
+    assert_eq!(bus.addresses().collect::<Vec<_>>(), vec![1, 2, 5]);
+
+    let request = JVSPacket::with_payload(HOST_ADDRESS, 2, vec![0x10]).unwrap();
+    assert_eq!(
+        bus.dispatch(&request),
+        Some(vec![
+            JVSPacket::with_payload(2, HOST_ADDRESS, vec![0x10]).unwrap()
+        ])
+    );
+
+    // Silent nodes still count as routed; missing ones don't.
+    assert_eq!(bus.dispatch(&JVSPacket::new(HOST_ADDRESS, 5)), Some(vec![]));
+    assert_eq!(bus.dispatch(&JVSPacket::new(HOST_ADDRESS, 7)), None);
+
+    // Broadcast replies come from each node's own address.
+    let replies = bus
+        .dispatch(&JVSPacket::new(HOST_ADDRESS, BROADCAST_ADDRESS))
+        .unwrap();
+    assert_eq!(
+        replies
+            .iter()
+            .map(|reply| reply.source_id)
+            .collect::<Vec<_>>(),
+        vec![1, 2]
+    );
+
+    assert!(bus.detach(1).is_some());
+    assert_eq!(bus.attach(echo), Ok(1));
+}
+
+#[test]
+fn test_led_parse_bounds() {
+    let packet = JVSPacket::with_payload(0x02, 0x01, vec![0x32, 0, 10, 1]).unwrap();
+    let err = sega_led::LEDCommand::parse(&packet).unwrap_err();
+    assert_eq!(err.to_string(), "SetMultiLED needs 7 body bytes, got 3");
+
+    let packet = JVSPacket::with_payload(0x02, 0x01, vec![0x31, 1, 2, 3, 4, 5]).unwrap();
+    let err = sega_led::LEDCommand::parse(&packet).unwrap_err();
+    assert_eq!(err.to_string(), "SetLED needs 4 body bytes, got 5");
+
+    // No opcode/length combination may panic.
+    for opcode in 0..=255u8 {
+        for len in 0..16 {
+            let mut payload = vec![0xAA; len + 1];
+            payload[0] = opcode;
+            let packet = JVSPacket::with_payload(0x02, 0x01, payload).unwrap();
+            let _ = sega_led::LEDCommand::parse(&packet);
+        }
+    }
+}
+
+#[test]
+fn test_led_reply_parse() {
+    let packet =
+        JVSPacket::with_payload(0x01, 0x02, b"\x01\xF0\x0115070-04\xff\x01\x02".to_vec()).unwrap();
+    let reply = LEDReply::parse(&packet).expect("board info reply");
+    assert!(reply.is_ok());
+    assert_eq!(reply.command(), Some(LEDCommandType::GetBoardInfoCommand));
+    assert_eq!(
+        reply.body,
+        LEDReplyBody::BoardInfo(BoardInfo {
+            part_number: "15070-04".to_owned(),
+            firmware_version: 1,
+            extra: vec![2],
+        })
+    );
+    let mut round_trip = JVSPacket::new(0x01, 0x02);
+    reply.serialize_to_jvs(&mut round_trip);
+    assert_eq!(round_trip, packet);
+
+    let packet = JVSPacket::with_payload(0x01, 0x02, vec![0x01, 0x31, 0x04]).unwrap();
+    let reply = LEDReply::parse(&packet).expect("ack");
+    assert_eq!(reply.report, ReplyReport::ParameterError);
+    assert_eq!(reply.body, LEDReplyBody::Ack);
+    assert!(!reply.is_ok());
+
+    let packet = JVSPacket::with_payload(0x01, 0x02, vec![0x01, 0x31]).unwrap();
+    assert!(LEDReply::parse(&packet).is_err());
+}
+
+#[test]
+fn test_set_fet() {
+    let packet = JVSPacket::with_payload(0x02, 0x01, vec![0x39, 0x10, 0x20, 0x30]).unwrap();
+    let cmd = sega_led::LEDCommand::parse(&packet).expect("set fet");
+    assert_eq!(
+        cmd,
+        sega_led::LEDCommand::SetFet(FetLevels {
+            chassis: 0x10,
+            ring: 0x20,
+            side: 0x30
+        })
+    );
+    let mut round_trip = JVSPacket::new(0x02, 0x01);
+    cmd.serialize_to_jvs(&mut round_trip);
+    assert_eq!(round_trip, packet);
+
+    let packet = JVSPacket::with_payload(0x02, 0x01, vec![0x39, 0x10, 0x20]).unwrap();
+    assert!(sega_led::LEDCommand::parse(&packet).is_err());
+}
+
+#[test]
+fn test_dot_correction() {
+    let packet =
+        JVSPacket::with_payload(0x02, 0x01, vec![0x3F, 2, 8, 1, 0x3F, 0x20, 0x10, 0]).unwrap();
+    let cmd = sega_led::LEDCommand::parse(&packet).expect("set dc");
+    let sega_led::LEDCommand::SetDc(dc) = &cmd else {
+        panic!("expected SetDc, got {:?}", cmd);
+    };
+    assert_eq!(dc.leds().collect::<Vec<_>>(), vec![2, 4, 6]);
+    assert_eq!(dc.for_led(4), Some([0x3F, 0x20, 0x10]));
+    assert_eq!(dc.for_led(3), None);
+    assert_eq!(dc.for_led(8), None);
+
+    let mut round_trip = JVSPacket::new(0x02, 0x01);
+    cmd.serialize_to_jvs(&mut round_trip);
+    assert_eq!(round_trip, packet);
+}
+
+#[test]
+fn test_board_info() {
+    let info = BoardInfo::new("15070-02", 0x90);
+    let mut buf = Vec::new();
+    info.serialize(&mut buf);
+    assert_eq!(buf, b"15070-02\xff\x90");
+    assert_eq!(BoardInfo::parse(&buf).unwrap(), info);
+    assert_eq!(info.to_string(), "15070-02 (firmware 144)");
+
+    assert!(BoardInfo::parse(b"15070-02").is_err());
+    assert!(BoardInfo::parse(b"15070-02\xff").is_err());
+}
+
+#[test]
+fn test_version_and_status_replies() {
+    let packet =
+        JVSPacket::with_payload(0x01, 0x02, vec![0x01, 0xF3, 0x01, 0x01, 0x01, 0x04]).unwrap();
+    let reply = LEDReply::parse(&packet).expect("protocol version reply");
+    assert_eq!(
+        reply.body,
+        LEDReplyBody::ProtocolVersion(ProtocolVersion {
+            mode: 1,
+            major: 1,
+            minor: 4
+        })
+    );
+    assert_eq!(
+        "1.4".parse::<ProtocolVersion>().unwrap(),
+        ProtocolVersion {
+            mode: 1,
+            major: 1,
+            minor: 4
+        }
+    );
+
+    let packet = JVSPacket::with_payload(0x01, 0x02, vec![0x01, 0xF1, 0x01, 0, 5, 0, 1]).unwrap();
+    let reply = LEDReply::parse(&packet).expect("board status reply");
+    let status = BoardStatus {
+        timeout_status: 0,
+        timeout_sec: 5,
+        pwm_io: 0,
+        fet_timeout: 1,
+    };
+    assert_eq!(reply.body, LEDReplyBody::BoardStatus(status));
+    assert_eq!("0, 5, 0, 1".parse::<BoardStatus>().unwrap(), status);
+    assert!("0,5,0".parse::<BoardStatus>().is_err());
+
+    let mut round_trip = JVSPacket::new(0x01, 0x02);
+    reply.serialize_to_jvs(&mut round_trip);
+    assert_eq!(round_trip, packet);
+}
+
+#[test]
+fn test_eeprom_commands() {
+    let packet = JVSPacket::with_payload(0x02, 0x01, vec![0x7B, 0x10, 0xAB]).unwrap();
+    let cmd = sega_led::LEDCommand::parse(&packet).expect("eeprom write");
+    assert_eq!(
+        cmd,
+        sega_led::LEDCommand::EepromWrite {
+            address: 0x10,
+            data: 0xAB
+        }
+    );
+    let mut round_trip = JVSPacket::new(0x02, 0x01);
+    cmd.serialize_to_jvs(&mut round_trip);
+    assert_eq!(round_trip, packet);
+
+    let packet = JVSPacket::with_payload(0x02, 0x01, vec![0x7C, 0x10]).unwrap();
+    let cmd = sega_led::LEDCommand::parse(&packet).expect("eeprom read");
+    assert_eq!(cmd, sega_led::LEDCommand::EepromRead { address: 0x10 });
+
+    let packet = JVSPacket::with_payload(0x01, 0x02, vec![0x01, 0x7C, 0x01, 0xAB]).unwrap();
+    let reply = LEDReply::parse(&packet).expect("eeprom read reply");
+    assert_eq!(reply.body, LEDReplyBody::EepromRead(0xAB));
+}
+
+#[test]
+fn test_set_timeout() {
+    let packet = JVSPacket::with_payload(0x02, 0x01, vec![0x11, 0x01, 0x2C]).unwrap();
+    let cmd = sega_led::LEDCommand::parse(&packet).expect("set timeout");
+    assert_eq!(cmd, sega_led::LEDCommand::SetTimeout { seconds: 300 });
+    let mut round_trip = JVSPacket::new(0x02, 0x01);
+    cmd.serialize_to_jvs(&mut round_trip);
+    assert_eq!(round_trip, packet);
+}
+
+#[test]
+fn test_unknown_command() {
+    let packet = JVSPacket::with_payload(0x02, 0x01, vec![0x99, 0x01, 0xE0, 0xD0]).unwrap();
+    let cmd = sega_led::LEDCommand::parse(&packet).expect("unknown command");
+    assert_eq!(
+        cmd,
+        sega_led::LEDCommand::Unknown {
+            opcode: 0x99,
+            body: vec![0x01, 0xE0, 0xD0]
+        }
+    );
+    assert_eq!(cmd.get_type(), None);
+    assert_eq!(cmd.opcode(), 0x99);
+
+    let mut round_trip = JVSPacket::new(0x02, 0x01);
+    cmd.serialize_to_jvs(&mut round_trip);
+    assert_eq!(round_trip, packet);
+
+    // The board's answer to it parses too.
+    let reply_packet =
+        JVSPacket::with_payload(0x01, 0x02, vec![0x01, 0x99, 0x01, 0x01, 0xE0, 0xD0]).unwrap();
+    let reply = LEDReply::parse(&reply_packet).expect("unknown command reply");
+    assert!(reply.is_ok());
+    assert_eq!(reply.opcode, 0x99);
+    assert_eq!(reply.command(), None);
+    assert_eq!(reply.body, LEDReplyBody::Other(vec![0x01, 0xE0, 0xD0]));
+    let mut round_trip = packet.reply();
+    reply.serialize_to_jvs(&mut round_trip);
+    assert_eq!(round_trip, reply_packet);
+}
+
+#[test]
+fn test_virtual_board_commit() {
+    use sega_led::LEDCommand;
+    let mut board = VirtualLedBoard::new();
+    board.apply(&LEDCommand::SetLED {
+        index: 3,
+        r: 0x10,
+        g: 0x20,
+        b: 0x30,
+    });
+    board.apply(&LEDCommand::SetMultiLED {
+        start: 10,
+        end: 16,
+        skip: 2,
+        r: 0xFF,
+        g: 0,
+        b: 0,
+        speed: 0,
+    });
+    assert_eq!(board.led(3), [0, 0, 0], "nothing shows before Commit");
+    board.apply(&LEDCommand::Commit);
+    assert_eq!(board.led(3), [0x10, 0x20, 0x30]);
+    let lit: Vec<_> = (0..=255u8)
+        .filter(|&i| board.led(i) == [0xFF, 0, 0])
+        .collect();
+    assert_eq!(lit, vec![10, 13]);
+
+    let fet = FetLevels {
+        chassis: 1,
+        ring: 2,
+        side: 3,
+    };
+    board.apply(&LEDCommand::SetFet(fet));
+    assert_eq!(board.fet(), fet);
+    board.apply(&LEDCommand::SetDc(sega_led::DotCorrection {
+        start: 0,
+        end: 2,
+        skip: 0,
+        r: 1,
+        g: 2,
+        b: 3,
+        speed: 0,
+    }));
+    assert_eq!(board.dot_correction(1), [1, 2, 3]);
+    assert_eq!(board.dot_correction(2), DEFAULT_DOT_CORRECTION);
+
+    board.apply(&LEDCommand::Reset);
+    assert!(board.leds().all(|led| led == [0, 0, 0]));
+    assert_eq!(board.fet(), FetLevels::default());
+    assert_eq!(board.dot_correction(1), DEFAULT_DOT_CORRECTION);
+}
+
+#[test]
+fn test_virtual_board_fade() {
+    use sega_led::LEDCommand;
+    let mut board = VirtualLedBoard::new();
+    let fade = |r| LEDCommand::SetMultiLEDFade {
+        start: 0,
+        end: 4,
+        skip: 0,
+        r,
+        g: 0,
+        b: 0,
+        speed: 0x80,
+    };
+    let length = led_board::fade_duration(0x80);
+    assert_eq!(length, Duration::from_millis(256));
+
+    board.apply(&fade(200));
+    board.apply(&LEDCommand::Commit);
+    assert!(board.is_fading());
+    assert_eq!(board.led(0), [0, 0, 0]);
+    board.advance(length / 2);
+    assert_eq!(board.led(0), [100, 0, 0]);
+    assert_eq!(board.led(4), [0, 0, 0]);
+
+    // A new fade starts from wherever the last one got to.
+    board.apply(&fade(0));
+    board.apply(&LEDCommand::Commit);
+    board.advance(length / 4);
+    assert_eq!(board.led(0), [75, 0, 0]);
+    board.advance(length);
+    assert_eq!(board.led(0), [0, 0, 0]);
+    assert!(!board.is_fading());
+}
+
+#[test]
+fn test_virtual_board_replay() {
+    use sega_led::LEDCommand;
+    let mut board = VirtualLedBoard::new();
+    for cmd in [
+        LEDCommand::SetMultiLED {
+            start: 0,
+            end: 0xFF,
+            skip: 3,
+            r: 0x40,
+            g: 0x50,
+            b: 0x60,
+            speed: 0,
+        },
+        LEDCommand::SetLED {
+            index: 0xFF,
+            r: 1,
+            g: 2,
+            b: 3,
+        },
+        LEDCommand::SetMultiLEDFade {
+            start: 5,
+            end: 9,
+            skip: 0,
+            r: 0xFF,
+            g: 0,
+            b: 0,
+            speed: 1,
+        },
+        LEDCommand::SetDc(sega_led::DotCorrection {
+            start: 16,
+            end: 32,
+            skip: 1,
+            r: 0x10,
+            g: 0x20,
+            b: 0x30,
+            speed: 0,
+        }),
+        LEDCommand::SetFet(FetLevels {
+            chassis: 4,
+            ring: 5,
+            side: 6,
+        }),
+        LEDCommand::Commit,
+    ] {
+        board.apply(&cmd);
+    }
+
+    // Fades are replayed at their target colour.
+    let mut restored = VirtualLedBoard::new();
+    for cmd in board.replay_commands() {
+        restored.apply(&cmd);
+    }
+    board.advance(led_board::fade_duration(1));
+    assert!(board.leds().eq(restored.leds()));
+    assert_eq!(restored.led(0xFF), [1, 2, 3]);
+    assert_eq!(restored.fet(), board.fet());
+    assert!((0..=0xFF).all(|i| restored.dot_correction(i) == board.dot_correction(i)));
 }
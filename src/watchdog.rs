@@ -0,0 +1,57 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+struct LastCommand {
+    at: Instant,
+    source_id: u8,
+    dest_id: u8,
+}
+
+/// Tracks when the game last sent the board anything, against the timeout it asked for.
+pub struct Watchdog {
+    default_timeout: Duration,
+    timeout: Mutex<Option<Duration>>,
+    last_command: Mutex<Option<LastCommand>>,
+}
+impl Watchdog {
+    pub fn new(default_timeout: Duration) -> Self {
+        Watchdog {
+            default_timeout,
+            timeout: Mutex::new(None),
+            last_command: Mutex::new(None),
+        }
+    }
+
+    /// Records a command from the game on its way to board `dest_id`, arriving at `now`.
+    pub fn feed(&self, source_id: u8, dest_id: u8, now: Instant) {
+        *self.last_command.lock().unwrap() = Some(LastCommand {
+            at: now,
+            source_id,
+            dest_id,
+        });
+    }
+
+    /// Applies the game's `SetTimeout`. Zero (no timeout on the board) falls back to our default.
+    pub fn set_timeout(&self, seconds: u16) {
+        *self.timeout.lock().unwrap() = (seconds != 0).then(|| Duration::from_secs(seconds as u64));
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout.lock().unwrap().unwrap_or(self.default_timeout)
+    }
+
+    /// `(source_id, dest_id)` of the game's last command, if it has sent any.
+    pub fn last_ids(&self) -> Option<(u8, u8)> {
+        let last = self.last_command.lock().unwrap();
+        last.as_ref().map(|last| (last.source_id, last.dest_id))
+    }
+
+    /// `(source_id, dest_id)` of the game's last command, if by `now` it went quiet longer than
+    /// the timeout. Never trips before the game has said anything.
+    pub fn expired(&self, now: Instant) -> Option<(u8, u8)> {
+        let timeout = self.timeout();
+        let last = self.last_command.lock().unwrap();
+        let last = last.as_ref()?;
+        (now.saturating_duration_since(last.at) > timeout).then_some((last.source_id, last.dest_id))
+    }
+}